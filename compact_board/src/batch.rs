@@ -0,0 +1,98 @@
+//! The shape of the evaluation batches in `../hugedata/batches`.
+//!
+//! Every entry is a board with White to move, its numeric score from White's perspective,
+//! and a move in UCI notation.
+//! Boards where Black is to move are rotated by 180 degrees and have their colours swapped,
+//! so that the old Black becomes the new White; the move is rotated in the same way.
+
+use alloc::string::{String, ToString};
+
+use shakmaty::{uci::Uci, Board, ByColor, Color, Square};
+
+use crate::evaluation::EngineEvaluation;
+use crate::CompactBoard;
+
+pub type BatchEntry = (CompactBoard, f32, String);
+
+/// The board as seen by the side to move, which plays White in the result.
+pub fn white_perspective(board: &Board, turn: Color) -> Board {
+    let mut board = board.clone();
+    if turn.is_white() {
+        return board;
+    }
+    board.rotate_180();
+    let (by_role, by_color) = board.into_bitboards();
+    Board::from_bitboards(
+        by_role,
+        ByColor {
+            black: by_color.white,
+            white: by_color.black,
+        },
+    )
+}
+
+/// The move on the board returned by [`white_perspective`].
+pub fn white_perspective_uci(uci: &Uci, turn: Color) -> Uci {
+    if turn.is_white() {
+        return uci.clone();
+    }
+    let rotate = |sq: Square| Square::new(63 - u32::from(sq));
+    match uci {
+        Uci::Normal {
+            from,
+            to,
+            promotion,
+        } => Uci::Normal {
+            from: rotate(*from),
+            to: rotate(*to),
+            promotion: *promotion,
+        },
+        Uci::Put { role, to } => Uci::Put {
+            role: *role,
+            to: rotate(*to),
+        },
+        Uci::Null => Uci::Null,
+    }
+}
+
+/// A batch entry for a position where `turn` is to move,
+/// given its evaluation from White's perspective and a move in the position.
+pub fn batch_entry(board: &Board, turn: Color, eval: EngineEvaluation, uci: &Uci) -> BatchEntry {
+    (
+        CompactBoard::from_board(&white_perspective(board, turn)),
+        eval.for_side(turn).to_numeric_score(),
+        white_perspective_uci(uci, turn).to_string(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use shakmaty::{Bitboard, Chess, Position};
+
+    use super::*;
+
+    #[test]
+    fn test_black_to_move_is_rotated() {
+        let mut pos = Chess::new();
+        let e4 = Uci::from_ascii(b"e2e4").unwrap().to_move(&pos).unwrap();
+        pos.play_unchecked(&e4);
+
+        let e5 = Uci::from_ascii(b"e7e5").unwrap();
+        let (board, score, uci) = batch_entry(
+            pos.board(),
+            pos.turn(),
+            EngineEvaluation::Centipawns(-40),
+            &e5,
+        );
+        assert_eq!(
+            board
+                .to_board()
+                .unwrap()
+                .board_fen(Bitboard::EMPTY)
+                .to_string(),
+            "rnbkqbnr/ppp1pppp/8/3p4/8/8/PPPPPPPP/RNBKQBNR"
+        );
+        assert!(score > 0.0);
+        assert_eq!(uci, "d2d4");
+    }
+}
@@ -0,0 +1,158 @@
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::Deref;
+use core::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use shakmaty::{Bitboard, Board};
+
+use crate::{
+    compact_slice_to_board, try_board_to_compact, try_compact_slice_to_board, CompactError,
+};
+
+/// A board in the layout of [`crate::board_to_compact`].
+///
+/// This serializes exactly like the bytes it holds,
+/// so files written with a bare `Vec<u8>` in its place can still be read.
+/// The ordering is the ordering of the bytes, which is also the order of the keys in the board tries.
+///
+/// Only the unversioned board layout, [`crate::FormatVersion::V1`] without a header byte, is wrapped.
+/// [`Display`](fmt::Display), [`FromStr`] and the decoders here all assume that layout,
+/// and would silently misread the other encodings, which can start with the same bytes.
+/// The position encoders ([`crate::position_to_compact`], [`crate::setup_to_compact`]),
+/// the versioned encoders in [`crate::format`] and the Huffman encoders in [`crate::huffman`]
+/// therefore still return `Vec<u8>`; they are only ever read back by their own decoders.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct CompactBoard(Vec<u8>);
+
+impl CompactBoard {
+    /// Encodes the board.
+    ///
+    /// Panics if a side does not have exactly one king; see [`CompactBoard::try_from_board`].
+    pub fn from_board(board: &Board) -> CompactBoard {
+        crate::board_to_compact(board)
+    }
+
+    pub fn try_from_board(board: &Board) -> Result<CompactBoard, CompactError> {
+        try_board_to_compact(board)
+    }
+
+    /// Wraps bytes that are already in the compact layout, without checking them.
+    pub fn from_bytes(bytes: Vec<u8>) -> CompactBoard {
+        CompactBoard(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.0
+    }
+
+    pub fn to_board(&self) -> Result<Board, bitreader::BitReaderError> {
+        compact_slice_to_board(&self.0)
+    }
+
+    /// See [`crate::try_compact_to_board`].
+    pub fn try_to_board(&self, strict: bool) -> Result<Board, CompactError> {
+        try_compact_slice_to_board(&self.0, strict)
+    }
+}
+
+impl Deref for CompactBoard {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl AsRef<[u8]> for CompactBoard {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<Vec<u8>> for CompactBoard {
+    fn from(bytes: Vec<u8>) -> Self {
+        CompactBoard(bytes)
+    }
+}
+
+impl From<CompactBoard> for Vec<u8> {
+    fn from(board: CompactBoard) -> Self {
+        board.0
+    }
+}
+
+/// Shows the board part of the FEN.
+///
+/// Bytes that do not decode to a board are shown in hex instead.
+impl fmt::Display for CompactBoard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.try_to_board(false) {
+            Ok(board) => write!(f, "{}", board.board_fen(Bitboard::EMPTY)),
+            Err(_) => {
+                write!(f, "invalid:")?;
+                for byte in &self.0 {
+                    write!(f, "{byte:02x}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Parses the board part of a FEN.
+/// A full FEN is also accepted, but everything after the board is ignored.
+impl FromStr for CompactBoard {
+    type Err = CompactError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let board_fen = s.split_whitespace().next().unwrap_or_default();
+        let board = Board::from_ascii_board_fen(board_fen.as_bytes())?;
+        CompactBoard::try_from_board(&board)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fen_round_trip() {
+        let fen = "rnbqkbnr/pp1ppppp/8/2p5/4P3/8/PPPP1PPP/RNBQKBNR";
+        let compact: CompactBoard = fen.parse().unwrap();
+        assert_eq!(compact.to_string(), fen);
+        assert_eq!(
+            compact,
+            CompactBoard::from_board(&compact.to_board().unwrap())
+        );
+
+        let full: CompactBoard = format!("{fen} b KQkq c6 0 2").parse().unwrap();
+        assert_eq!(full, compact);
+
+        assert!("8/8/8/8/8/8/8/4K3".parse::<CompactBoard>().is_err());
+        assert!("not a fen".parse::<CompactBoard>().is_err());
+    }
+
+    #[test]
+    fn test_ordering_matches_bytes() {
+        let a: CompactBoard = "4k3/8/8/8/8/8/8/4K3".parse().unwrap();
+        let b: CompactBoard = "4k3/8/8/8/8/8/8/3QK3".parse().unwrap();
+        assert_eq!(a.cmp(&b), a.as_bytes().cmp(b.as_bytes()));
+    }
+
+    #[test]
+    fn test_serializes_like_bytes() {
+        let compact = CompactBoard::from_board(&Board::new());
+        let batch = vec![(compact.clone(), 0.5f32, "e2e4".to_string())];
+        let legacy = vec![(compact.as_bytes().to_vec(), 0.5f32, "e2e4".to_string())];
+        let bytes = postcard::to_stdvec(&batch).unwrap();
+        assert_eq!(bytes, postcard::to_stdvec(&legacy).unwrap());
+        let decoded: Vec<(CompactBoard, f32, String)> = postcard::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded, batch);
+    }
+}
@@ -0,0 +1,74 @@
+use alloc::boxed::Box;
+use core::fmt;
+
+use shakmaty::{fen::ParseFenError, Chess, Color, PositionError, Square};
+
+/// Errors that can happen while encoding or decoding compact data.
+#[derive(Debug)]
+pub enum CompactError {
+    /// The data ended before the layout was complete.
+    Bits(bitreader::BitReaderError),
+    /// The bits were read successfully, but they describe an impossible position.
+    IllegalPosition(Box<PositionError<Chess>>),
+    /// Versioned data was empty, so it did not even have a header.
+    MissingHeader,
+    /// The header of versioned data names a layout that this version of the crate does not know.
+    UnknownFormatVersion(u8),
+    /// A side has no king, or more than one, so the board cannot be encoded.
+    KingCount { color: Color, count: usize },
+    /// The data puts more than one piece on the same square.
+    SquareOccupiedTwice(Square),
+    /// A pawn is on the first or last rank.
+    PawnOnBackRank(Square),
+    /// A side has more than 16 pieces.
+    TooManyPieces { color: Color, count: usize },
+    /// A board given as text is not a valid FEN.
+    InvalidFen(ParseFenError),
+}
+
+impl fmt::Display for CompactError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompactError::Bits(e) => write!(f, "could not read compact data: {e}"),
+            CompactError::IllegalPosition(e) => {
+                write!(f, "compact data is not a legal position: {e}")
+            }
+            CompactError::MissingHeader => write!(f, "versioned compact data is empty"),
+            CompactError::UnknownFormatVersion(tag) => {
+                write!(f, "unknown compact format version {tag}")
+            }
+            CompactError::KingCount { color, count } => {
+                write!(f, "{color} has {count} kings instead of one")
+            }
+            CompactError::SquareOccupiedTwice(square) => {
+                write!(f, "more than one piece is stored on {square}")
+            }
+            CompactError::PawnOnBackRank(square) => write!(f, "pawn on back rank square {square}"),
+            CompactError::TooManyPieces { color, count } => {
+                write!(f, "{color} has {count} pieces, more than 16")
+            }
+            CompactError::InvalidFen(e) => write!(f, "invalid board FEN: {e}"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for CompactError {}
+
+impl From<bitreader::BitReaderError> for CompactError {
+    fn from(e: bitreader::BitReaderError) -> Self {
+        CompactError::Bits(e)
+    }
+}
+
+impl From<PositionError<Chess>> for CompactError {
+    fn from(e: PositionError<Chess>) -> Self {
+        CompactError::IllegalPosition(Box::new(e))
+    }
+}
+
+impl From<ParseFenError> for CompactError {
+    fn from(e: ParseFenError) -> Self {
+        CompactError::InvalidFen(e)
+    }
+}
@@ -0,0 +1,103 @@
+//! Engine evaluations, as given by Stockfish or in the `[%eval]` annotations of lichess games,
+//! and the numeric scores of the evaluation batches.
+
+use alloc::vec::Vec;
+
+use shakmaty::Color;
+
+#[derive(Debug, Clone, Copy)]
+pub enum EngineEvaluation {
+    /// This amount of advantage to white
+    Centipawns(i64),
+    /// Mate in this number of moves
+    Mate(i64),
+}
+
+fn translate(value: f32, left_min: f32, left_max: f32, right_min: f32, right_max: f32) -> f32 {
+    let left_span = left_max - left_min;
+    let right_span = right_max - right_min;
+    let value_scaled = (value - left_min) / left_span;
+    right_min + (value_scaled * right_span)
+}
+
+impl EngineEvaluation {
+    pub fn to_numeric_score(&self) -> f32 {
+        match self {
+            EngineEvaluation::Centipawns(v) => {
+                // The range between -3000 and 3000 centipawns is mapped into -0.8 to 0.8
+                let v = (*v).clamp(-3000, 3000) as f32;
+                translate(v, -3000.0, 3000.0, -0.8, 0.8)
+            }
+            EngineEvaluation::Mate(t) => {
+                // The range between mate-in 25 and 1 is mapped to 0.8 to 1.
+                let inv_fac = translate((*t).clamp(1, 25) as f32, 1.0, 25.0, 0.0, 0.2);
+                (if t.is_negative() { -1.0 } else { 1.0 }) - inv_fac
+            }
+        }
+    }
+
+    pub fn from_numeric_score(v: f32) -> Self {
+        // If the value is between -0.8 and 0.8, then it's a centipawns between -3000 and 3000
+        if v.abs() < 0.8 {
+            Self::Centipawns(translate(v, -0.8, 0.8, -3000.0, 3000.0) as i64)
+        } else {
+            let fac = v.abs() - 0.8;
+            let inv_fac = 0.2 - fac;
+            Self::Mate(translate(inv_fac, 0.0, 0.2, 1.0, 25.0).ceil() as i64)
+        }
+    }
+}
+
+impl EngineEvaluation {
+    pub fn from_str(text: &str, to_move: Color) -> Self {
+        let parts: Vec<_> = text.trim().split(" ").collect();
+        let number: i64 = parts[1].parse().unwrap();
+        match parts[0] {
+            "cp" => Self::Centipawns(number * to_move.fold_wb(1, -1)),
+            "mate" => Self::Mate(number * to_move.fold_wb(1, -1)),
+            other => panic!("Unknown scoring: {other}"),
+        }
+    }
+}
+
+impl EngineEvaluation {
+    /// The same evaluation, but from the perspective of the given side instead of White.
+    pub fn for_side(self, color: Color) -> Self {
+        let sign = color.fold_wb(1, -1);
+        match self {
+            EngineEvaluation::Centipawns(v) => EngineEvaluation::Centipawns(v * sign),
+            EngineEvaluation::Mate(t) => EngineEvaluation::Mate(t * sign),
+        }
+    }
+
+    /// Finds an evaluation annotation in a PGN comment, as written by lichess:
+    /// `[%eval 0.35]` in pawns or `[%eval #-3]` for a mate, both from White's perspective.
+    pub fn from_pgn_comment(comment: &str) -> Option<Self> {
+        let (_, rest) = comment.split_once("[%eval ")?;
+        let (value, _) = rest.split_once(']')?;
+        // Some annotations also carry the search depth, as in `[%eval 0.35,20]`.
+        let value = value.split(',').next()?.trim();
+        if let Some(mate) = value.strip_prefix('#') {
+            return mate.parse().ok().map(Self::Mate);
+        }
+        let pawns: f64 = value.parse().ok()?;
+        Some(Self::Centipawns((pawns * 100.0).round() as i64))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pgn_comment_evals() {
+        let eval = EngineEvaluation::from_pgn_comment(" [%eval 0.35] [%clk 0:03:00] ");
+        assert!(matches!(eval, Some(EngineEvaluation::Centipawns(35))));
+        let eval = EngineEvaluation::from_pgn_comment("[%eval -1.2,18]");
+        assert!(matches!(eval, Some(EngineEvaluation::Centipawns(-120))));
+        let eval = EngineEvaluation::from_pgn_comment("[%eval #-3]");
+        assert!(matches!(eval, Some(EngineEvaluation::Mate(-3))));
+        assert!(EngineEvaluation::from_pgn_comment("[%clk 0:03:00]").is_none());
+        assert!(EngineEvaluation::from_pgn_comment("[%eval ?]").is_none());
+    }
+}
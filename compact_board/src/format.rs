@@ -0,0 +1,152 @@
+//! Self-describing compact data.
+//!
+//! The unversioned functions in this crate write only the layout bits,
+//! so the bytes themselves do not say how they should be read.
+//! The versioned functions prefix the layout with a single header byte
+//! holding the [`FormatVersion`], and the decoders dispatch on it,
+//! so data written with an older layout stays readable after the layout changes.
+//!
+//! Data written before the header was introduced has no header byte,
+//! and has to be read with the unversioned functions, which implement [`FormatVersion::V1`].
+
+use alloc::{vec, vec::Vec};
+
+use shakmaty::{Board, Chess};
+
+use crate::{
+    compact_to_position, huffman_to_board, huffman_to_position, try_compact_to_board, CompactError,
+};
+
+/// The layout that follows the header byte.
+///
+/// The discriminant is the value of the header byte.
+/// The value 0 is never used, so that zeroed memory is not mistaken for a valid header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum FormatVersion {
+    /// The kings first, then the other pieces of each colour grouped by role.
+    /// See [`crate::board_to_compact`] and [`crate::position`].
+    V1 = 1,
+    /// An occupancy bitboard followed by a prefix code for each piece.
+    /// See [`crate::huffman`].
+    V2 = 2,
+}
+
+impl FormatVersion {
+    /// The version used when there is no reason to use any other.
+    pub const LATEST: FormatVersion = FormatVersion::V1;
+
+    pub fn from_tag(tag: u8) -> Option<FormatVersion> {
+        match tag {
+            1 => Some(FormatVersion::V1),
+            2 => Some(FormatVersion::V2),
+            _ => None,
+        }
+    }
+
+    pub fn tag(self) -> u8 {
+        self as u8
+    }
+}
+
+/// Reads the header of versioned compact data.
+pub fn format_version(data: &[u8]) -> Result<FormatVersion, CompactError> {
+    let tag = *data.first().ok_or(CompactError::MissingHeader)?;
+    FormatVersion::from_tag(tag).ok_or(CompactError::UnknownFormatVersion(tag))
+}
+
+pub fn board_to_versioned_compact(board: &Board, version: FormatVersion) -> Vec<u8> {
+    let mut output = vec![version.tag()];
+    match version {
+        FormatVersion::V1 => output.extend_from_slice(&crate::board_to_compact(board)),
+        FormatVersion::V2 => output.extend(crate::board_to_huffman(board)),
+    }
+    output
+}
+
+pub fn versioned_compact_to_board(data: &[u8]) -> Result<Board, CompactError> {
+    let version = format_version(data)?;
+    let mut r = bitreader::BitReader::new(&data[1..]);
+    match version {
+        FormatVersion::V1 => try_compact_to_board(&mut r, false),
+        FormatVersion::V2 => huffman_to_board(&mut r),
+    }
+}
+
+pub fn position_to_versioned_compact(
+    pos: &Chess,
+    with_clocks: bool,
+    version: FormatVersion,
+) -> Vec<u8> {
+    let mut output = vec![version.tag()];
+    match version {
+        FormatVersion::V1 => output.extend(crate::position_to_compact(pos, with_clocks)),
+        FormatVersion::V2 => output.extend(crate::position_to_huffman(pos, with_clocks)),
+    }
+    output
+}
+
+pub fn versioned_compact_to_position(data: &[u8]) -> Result<Chess, CompactError> {
+    let version = format_version(data)?;
+    let mut r = bitreader::BitReader::new(&data[1..]);
+    match version {
+        FormatVersion::V1 => compact_to_position(&mut r),
+        FormatVersion::V2 => huffman_to_position(&mut r),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use shakmaty::Position;
+
+    use super::*;
+
+    #[test]
+    fn test_versioned_round_trip() {
+        let b = Board::new();
+        let compact_repr = board_to_versioned_compact(&b, FormatVersion::V1);
+        assert_eq!(compact_repr[0], 1);
+        assert_eq!(&compact_repr[1..], &crate::board_to_compact(&b)[..]);
+        assert_eq!(versioned_compact_to_board(&compact_repr).unwrap(), b);
+
+        let pos = Chess::default();
+        let compact_repr = position_to_versioned_compact(&pos, true, FormatVersion::LATEST);
+        assert_eq!(
+            format_version(&compact_repr).unwrap(),
+            FormatVersion::LATEST
+        );
+        let decoded = versioned_compact_to_position(&compact_repr).unwrap();
+        assert_eq!(decoded.board(), pos.board());
+    }
+
+    #[test]
+    fn test_versions_agree() {
+        let pos = Chess::default();
+        for version in [FormatVersion::V1, FormatVersion::V2] {
+            let compact_repr = board_to_versioned_compact(pos.board(), version);
+            assert_eq!(format_version(&compact_repr).unwrap(), version);
+            assert_eq!(
+                &versioned_compact_to_board(&compact_repr).unwrap(),
+                pos.board()
+            );
+
+            let compact_repr = position_to_versioned_compact(&pos, false, version);
+            let decoded = versioned_compact_to_position(&compact_repr).unwrap();
+            assert_eq!(decoded.board(), pos.board());
+        }
+    }
+
+    #[test]
+    fn test_unknown_version() {
+        assert!(matches!(
+            versioned_compact_to_board(&[]),
+            Err(CompactError::MissingHeader)
+        ));
+        let mut compact_repr = board_to_versioned_compact(&Board::new(), FormatVersion::V1);
+        compact_repr[0] = 0;
+        assert!(matches!(
+            versioned_compact_to_board(&compact_repr),
+            Err(CompactError::UnknownFormatVersion(0))
+        ));
+    }
+}
@@ -0,0 +1,281 @@
+//! Fixed-width Zobrist hashes of boards and positions.
+//!
+//! A hash is the XOR of a random key for every piece on every square,
+//! plus keys for the side to move, every castling right and the en passant file.
+//! This makes it cheap to update the hash when a move is played,
+//! since only the keys of the squares that changed need to be toggled.
+//!
+//! The keys are generated here from a fixed seed instead of taken from shakmaty,
+//! so the hashes stay the same across library versions and can be stored in files.
+//! The hash is 128 bits wide; the lower 64 bits are a valid hash on their own.
+
+use shakmaty::{
+    Board, Chess, Color, EnPassantMode, File, Move, Piece, Position, Rank, Role, Square,
+};
+
+use crate::position::CASTLING_ROOKS;
+
+const PIECE_KEYS: usize = 2 * 6 * 64;
+const CASTLING_KEYS: usize = PIECE_KEYS;
+const EN_PASSANT_KEYS: usize = CASTLING_KEYS + 4;
+const TURN_KEY: usize = EN_PASSANT_KEYS + 8;
+const KEY_COUNT: usize = TURN_KEY + 1;
+
+const KEYS: [u128; KEY_COUNT] = generate_keys();
+
+/// Fills the key table using the splitmix64 generator.
+const fn generate_keys() -> [u128; KEY_COUNT] {
+    let mut keys = [0; KEY_COUNT];
+    let mut state: u64 = 0x6e6e_2d63_6865_7373;
+    let mut idx = 0;
+    while idx < KEY_COUNT {
+        let mut halves = [0u64; 2];
+        let mut half = 0;
+        while half < 2 {
+            state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            halves[half] = z ^ (z >> 31);
+            half += 1;
+        }
+        keys[idx] = ((halves[0] as u128) << 64) | halves[1] as u128;
+        idx += 1;
+    }
+    keys
+}
+
+fn piece_key(color: Color, role: Role, sq: Square) -> u128 {
+    let piece_idx = color.fold_wb(0, 6) + (role as usize - 1);
+    KEYS[piece_idx * 64 + sq as usize]
+}
+
+fn castling_key(rook: Square) -> u128 {
+    CASTLING_ROOKS
+        .iter()
+        .position(|r| *r == rook)
+        .map_or(0, |idx| KEYS[CASTLING_KEYS + idx])
+}
+
+fn en_passant_key(file: File) -> u128 {
+    KEYS[EN_PASSANT_KEYS + file as usize]
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PositionHash(pub u128);
+
+impl PositionHash {
+    /// Hashes only the pieces on the board.
+    pub fn of_board(board: &Board) -> PositionHash {
+        let mut hash = 0;
+        for color in [Color::White, Color::Black] {
+            for role in Role::ALL {
+                for sq in board.by_piece(Piece { color, role }) {
+                    hash ^= piece_key(color, role, sq);
+                }
+            }
+        }
+        PositionHash(hash)
+    }
+
+    /// Hashes the board together with the side to move, castling rights
+    /// and en passant file. The move counters are not included.
+    ///
+    /// The en passant file is included whenever an enemy pawn stands next to
+    /// the pawn that has just moved two squares, like [`EnPassantMode::PseudoLegal`].
+    pub fn of_position(pos: &Chess) -> PositionHash {
+        let mut hash = PositionHash::of_board(pos.board()).0;
+        if pos.turn() == Color::Black {
+            hash ^= KEYS[TURN_KEY];
+        }
+        for rook in pos.castles().castling_rights() {
+            hash ^= castling_key(rook);
+        }
+        if let Some(ep) = pos.ep_square(EnPassantMode::PseudoLegal) {
+            hash ^= en_passant_key(ep.file());
+        }
+        PositionHash(hash)
+    }
+
+    /// The hash of the board after `m` is played in `before`,
+    /// given that `self` is the board hash of `before`.
+    pub fn board_after_move(self, before: &Chess, m: &Move) -> PositionHash {
+        PositionHash(self.0 ^ board_delta(before.turn(), m))
+    }
+
+    /// The hash of the position after `m` is played in `before`,
+    /// given that `self` is the position hash of `before`.
+    pub fn position_after_move(self, before: &Chess, m: &Move) -> PositionHash {
+        let turn = before.turn();
+        let mut hash = self.0 ^ board_delta(turn, m) ^ KEYS[TURN_KEY];
+
+        // Castling rights are lost when the king moves,
+        // or when anything moves from or to the square of the rook.
+        let (from, to, role) = match m {
+            Move::Normal { role, from, to, .. } => (Some(*from), *to, *role),
+            Move::EnPassant { from, to } => (Some(*from), *to, Role::Pawn),
+            Move::Castle { king, rook } => (Some(*king), *rook, Role::King),
+            Move::Put { role, to } => (None, *to, *role),
+        };
+        for rook in before.castles().castling_rights() {
+            let lost = Some(rook) == from
+                || rook == to
+                || (role == Role::King && rook.rank() == turn.fold_wb(Rank::First, Rank::Eighth));
+            if lost {
+                hash ^= castling_key(rook);
+            }
+        }
+
+        if let Some(ep) = before.ep_square(EnPassantMode::PseudoLegal) {
+            hash ^= en_passant_key(ep.file());
+        }
+        if let Move::Normal {
+            role: Role::Pawn,
+            from,
+            to,
+            ..
+        } = m
+        {
+            let pushed_two = (from.rank() as i32 - to.rank() as i32).abs() == 2;
+            if pushed_two {
+                let their_pawns = before.board().by_piece(Piece {
+                    color: turn.other(),
+                    role: Role::Pawn,
+                });
+                let next_to_pawn = [-1, 1]
+                    .into_iter()
+                    .filter_map(|offset| to.file().offset(offset))
+                    .any(|file| their_pawns.contains(Square::from_coords(file, to.rank())));
+                if next_to_pawn {
+                    hash ^= en_passant_key(to.file());
+                }
+            }
+        }
+
+        PositionHash(hash)
+    }
+
+    pub fn as_u128(self) -> u128 {
+        self.0
+    }
+
+    pub fn as_u64(self) -> u64 {
+        self.0 as u64
+    }
+}
+
+/// The keys of the pieces that change places when `m` is played by `turn`.
+fn board_delta(turn: Color, m: &Move) -> u128 {
+    let them = turn.other();
+    match m {
+        Move::Normal {
+            role,
+            from,
+            capture,
+            to,
+            promotion,
+        } => {
+            let mut delta = piece_key(turn, *role, *from);
+            if let Some(capture) = capture {
+                delta ^= piece_key(them, *capture, *to);
+            }
+            delta ^ piece_key(turn, promotion.unwrap_or(*role), *to)
+        }
+        Move::EnPassant { from, to } => {
+            let captured = Square::from_coords(to.file(), from.rank());
+            piece_key(turn, Role::Pawn, *from)
+                ^ piece_key(turn, Role::Pawn, *to)
+                ^ piece_key(them, Role::Pawn, captured)
+        }
+        Move::Castle { king, rook } => {
+            let (king_file, rook_file) = if rook.file() > king.file() {
+                (File::G, File::F)
+            } else {
+                (File::C, File::D)
+            };
+            piece_key(turn, Role::King, *king)
+                ^ piece_key(
+                    turn,
+                    Role::King,
+                    Square::from_coords(king_file, king.rank()),
+                )
+                ^ piece_key(turn, Role::Rook, *rook)
+                ^ piece_key(
+                    turn,
+                    Role::Rook,
+                    Square::from_coords(rook_file, rook.rank()),
+                )
+        }
+        Move::Put { role, to } => piece_key(turn, *role, *to),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use shakmaty::{fen::Fen, CastlingMode};
+
+    use super::*;
+
+    /// Plays a deterministic sequence of moves and checks that the incremental hashes
+    /// always match the ones computed from scratch.
+    fn check_game(fen: &str, seed: usize, plies: usize) {
+        let mut pos: Chess = Fen::from_ascii(fen.as_bytes())
+            .unwrap()
+            .into_position(CastlingMode::Standard)
+            .unwrap();
+        let mut board_hash = PositionHash::of_board(pos.board());
+        let mut position_hash = PositionHash::of_position(&pos);
+        for ply in 0..plies {
+            let moves = pos.legal_moves();
+            if moves.is_empty() {
+                break;
+            }
+            let m = moves[(ply * 7 + seed) % moves.len()].clone();
+            board_hash = board_hash.board_after_move(&pos, &m);
+            position_hash = position_hash.position_after_move(&pos, &m);
+            pos.play_unchecked(&m);
+            assert_eq!(board_hash, PositionHash::of_board(pos.board()));
+            assert_eq!(position_hash, PositionHash::of_position(&pos));
+        }
+    }
+
+    #[test]
+    fn test_incremental_hash() {
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        for seed in 0..20 {
+            check_game(start, seed, 200);
+        }
+        // Positions where castling, en passant and promotion are likely.
+        check_game("r3k2r/pppppppp/8/8/8/8/PPPPPPPP/R3K2R w KQkq - 0 1", 3, 60);
+        check_game("4k3/8/8/8/1p1p1p1p/8/P1P1P1P1/4K3 w - - 0 1", 1, 60);
+        check_game("4k3/P1P1P1P1/8/8/8/8/p1p1p1p1/4K3 w - - 0 1", 2, 40);
+    }
+
+    #[test]
+    fn test_hash_distinguishes_state() {
+        let white = Fen::from_ascii(b"4k3/8/8/8/8/8/8/4K2R w K - 0 1")
+            .unwrap()
+            .into_position::<Chess>(CastlingMode::Standard)
+            .unwrap();
+        let black = Fen::from_ascii(b"4k3/8/8/8/8/8/8/4K2R b K - 0 1")
+            .unwrap()
+            .into_position::<Chess>(CastlingMode::Standard)
+            .unwrap();
+        let no_castling = Fen::from_ascii(b"4k3/8/8/8/8/8/8/4K2R w - - 0 1")
+            .unwrap()
+            .into_position::<Chess>(CastlingMode::Standard)
+            .unwrap();
+        assert_eq!(
+            PositionHash::of_board(white.board()),
+            PositionHash::of_board(black.board())
+        );
+        assert_ne!(
+            PositionHash::of_position(&white),
+            PositionHash::of_position(&black)
+        );
+        assert_ne!(
+            PositionHash::of_position(&white),
+            PositionHash::of_position(&no_castling)
+        );
+    }
+}
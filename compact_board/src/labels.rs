@@ -0,0 +1,72 @@
+//! Training labels taken from the games themselves, instead of from an engine.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use serde::{Deserialize, Serialize};
+use shakmaty::Color;
+
+/// The final result of a game, as given by its `Result` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GameResult {
+    WhiteWins,
+    BlackWins,
+    Draw,
+    /// The game was unfinished, or the header was missing.
+    Unknown,
+}
+
+impl GameResult {
+    pub fn from_header(value: &str) -> GameResult {
+        match value {
+            "1-0" => GameResult::WhiteWins,
+            "0-1" => GameResult::BlackWins,
+            "1/2-1/2" => GameResult::Draw,
+            _ => GameResult::Unknown,
+        }
+    }
+
+    /// The result from the point of view of one side:
+    /// 1 for a win, 0 for a draw and -1 for a loss.
+    pub fn score_for(self, color: Color) -> Option<f32> {
+        let white_score = match self {
+            GameResult::WhiteWins => 1.0,
+            GameResult::BlackWins => -1.0,
+            GameResult::Draw => 0.0,
+            GameResult::Unknown => return None,
+        };
+        Some(color.fold_wb(white_score, -white_score))
+    }
+}
+
+/// One position of a game, together with what happened next.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LabelRecord {
+    /// The position before the move, as written by [`crate::position_to_compact`] without clocks.
+    pub position: Vec<u8>,
+    /// The move that was played in the position, in UCI notation.
+    pub uci: String,
+    pub result: GameResult,
+    pub white_elo: Option<u16>,
+    pub black_elo: Option<u16>,
+    /// The number of half-moves played before the position, so 0 is the starting position.
+    pub ply: u16,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_result_scores() {
+        let result = GameResult::from_header("0-1");
+        assert_eq!(result, GameResult::BlackWins);
+        assert_eq!(result.score_for(Color::Black), Some(1.0));
+        assert_eq!(result.score_for(Color::White), Some(-1.0));
+        assert_eq!(
+            GameResult::from_header("1/2-1/2").score_for(Color::White),
+            Some(0.0)
+        );
+        assert_eq!(GameResult::from_header("*").score_for(Color::White), None);
+    }
+}
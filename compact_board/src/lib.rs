@@ -1,3 +1,13 @@
+//! Without the default `std` feature, this crate is `no_std` and only needs `alloc`,
+//! so it can also be built for `wasm32-unknown-unknown`.
+//! The record files need `std::io`, so they are only available with `std`.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(not(feature = "alloc"))]
+compile_error!("compact_board needs at least the `alloc` feature");
+
+extern crate alloc;
+
 use bitvec::prelude::Msb0;
 use bitvec::vec::BitVec;
 use shakmaty::Color::*;
@@ -7,10 +17,62 @@ use shakmaty::Role::*;
 use shakmaty::Square;
 use shakmaty::{Board, Piece};
 
-pub fn board_to_compact(board: &Board) -> Vec<u8> {
+// The batches and evaluations round their scores with the float functions of `std`.
+#[cfg(feature = "std")]
+pub mod batch;
+mod compact;
+mod error;
+#[cfg(feature = "std")]
+pub mod evaluation;
+pub mod format;
+pub mod hash;
+pub mod huffman;
+pub mod labels;
+pub mod position;
+#[cfg(feature = "std")]
+pub mod records;
+pub mod stats;
+pub mod symmetry;
+mod validate;
+pub mod variant;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use compact::CompactBoard;
+pub use error::CompactError;
+pub use format::{
+    board_to_versioned_compact, format_version, position_to_versioned_compact,
+    versioned_compact_to_board, versioned_compact_to_position, FormatVersion,
+};
+pub use hash::PositionHash;
+pub use huffman::{
+    board_to_huffman, huffman_slice_to_board, huffman_to_board, huffman_to_position,
+    position_to_huffman,
+};
+pub use labels::{GameResult, LabelRecord};
+pub use position::{compact_slice_to_position, compact_to_position, position_to_compact};
+#[cfg(feature = "std")]
+pub use records::{
+    check_record_count, write_trie_as_records, IndexedRecordReader, MergedRecords, RecordReader,
+    RecordWriter,
+};
+pub use stats::PositionStats;
+pub use symmetry::{canonicalize_board, canonicalize_position, Transform};
+pub use validate::validate_board;
+pub use variant::{compact_slice_to_setup, compact_to_setup, setup_to_compact};
+
+pub fn board_to_compact(board: &Board) -> CompactBoard {
     let mut output: BitVec<u8, Msb0> = BitVec::with_capacity(248);
+    push_board(&mut output, board);
+
+    output.set_uninitialized(true);
+    CompactBoard::from_bytes(output.into_vec())
+}
+
+/// Appends the bits of the compact board layout to `output`, without padding.
+fn push_board(output: &mut BitVec<u8, Msb0>, board: &Board) {
     let mut push = |value, count| {
-        push_tail_bits(&mut output, value, count);
+        push_tail_bits(output, value, count);
     };
     // The first 4 bits show how many white pieces there are on the board,
     // the next 4 bits are how many black pieces there are.
@@ -48,13 +110,58 @@ pub fn board_to_compact(board: &Board) -> Vec<u8> {
             push(0, 1);
         }
     }
-
-    output.set_uninitialized(true);
-    output.into_vec()
 }
 
 pub fn compact_to_board(r: &mut bitreader::BitReader) -> Result<Board, bitreader::BitReaderError> {
+    read_board(r).map(|(b, _)| b)
+}
+
+/// Like [`board_to_compact`], but fails instead of panicking
+/// when a side does not have exactly one king.
+pub fn try_board_to_compact(board: &Board) -> Result<CompactBoard, CompactError> {
+    for color in [White, Black] {
+        let count = board.by_piece(Piece { color, role: King }).count();
+        if count != 1 {
+            return Err(CompactError::KingCount { color, count });
+        }
+    }
+    Ok(board_to_compact(board))
+}
+
+/// Like [`compact_to_board`], but fails if two pieces are stored on the same square,
+/// instead of silently keeping the last one.
+///
+/// If `strict` is set, the board also has to pass [`validate_board`].
+pub fn try_compact_to_board(
+    r: &mut bitreader::BitReader,
+    strict: bool,
+) -> Result<Board, CompactError> {
+    let (b, overlap) = read_board(r)?;
+    if let Some(square) = overlap {
+        return Err(CompactError::SquareOccupiedTwice(square));
+    }
+    if strict {
+        validate_board(&b)?;
+    }
+    Ok(b)
+}
+
+pub fn try_compact_slice_to_board(r: &[u8], strict: bool) -> Result<Board, CompactError> {
+    try_compact_to_board(&mut bitreader::BitReader::new(r), strict)
+}
+
+/// Reads the board, also returning the first square that had more than one piece put on it.
+fn read_board(
+    r: &mut bitreader::BitReader,
+) -> Result<(Board, Option<Square>), bitreader::BitReaderError> {
     let mut b = Board::empty();
+    let mut overlap = None;
+    let mut place = |b: &mut Board, square: Square, piece: Piece| {
+        if overlap.is_none() && b.piece_at(square).is_some() {
+            overlap = Some(square);
+        }
+        b.set_piece_at(square, piece);
+    };
 
     // The first 4 bits is the number of white pieces, then number of black pieces. All zeros means 16.
     // let mut white_pieces_left = r.read_u8(4)?;
@@ -69,7 +176,8 @@ pub fn compact_to_board(r: &mut bitreader::BitReader) -> Result<Board, bitreader
     let wkf = r.read_u8(3)?;
     let wkr = r.read_u8(3)?;
     let wk = Square::from_coords(File::new(wkf as u32), Rank::new(wkr as u32));
-    b.set_piece_at(
+    place(
+        &mut b,
         wk,
         Piece {
             color: White,
@@ -80,7 +188,8 @@ pub fn compact_to_board(r: &mut bitreader::BitReader) -> Result<Board, bitreader
     let bkf = r.read_u8(3)?;
     let bkr = r.read_u8(3)?;
     let bk = Square::from_coords(File::new(bkf as u32), Rank::new(bkr as u32));
-    b.set_piece_at(
+    place(
+        &mut b,
         bk,
         Piece {
             color: Black,
@@ -95,7 +204,7 @@ pub fn compact_to_board(r: &mut bitreader::BitReader) -> Result<Board, bitreader
             let pf = r.read_u8(3)?;
             let pr = r.read_u8(3)?;
             let p = Square::from_coords(File::new(pf as u32), Rank::new(pr as u32));
-            b.set_piece_at(p, Piece { color: White, role });
+            place(&mut b, p, Piece { color: White, role });
         }
     }
 
@@ -107,11 +216,11 @@ pub fn compact_to_board(r: &mut bitreader::BitReader) -> Result<Board, bitreader
             let pf = r.read_u8(3)?;
             let pr = r.read_u8(3)?;
             let p = Square::from_coords(File::new(pf as u32), Rank::new(pr as u32));
-            b.set_piece_at(p, Piece { color: Black, role });
+            place(&mut b, p, Piece { color: Black, role });
         }
     }
 
-    Ok(b)
+    Ok((b, overlap))
 }
 
 pub fn compact_slice_to_board(r: &[u8]) -> Result<Board, bitreader::BitReaderError> {
@@ -120,7 +229,7 @@ pub fn compact_slice_to_board(r: &[u8]) -> Result<Board, bitreader::BitReaderErr
 
 fn push_tail_bits(to_where: &mut BitVec<u8, Msb0>, value: u8, tail_bit_count: u8) {
     for idx in (0..tail_bit_count).rev() {
-        let is_bit_set: bool = unsafe { std::mem::transmute((value >> idx) & 1) };
+        let is_bit_set: bool = unsafe { core::mem::transmute((value >> idx) & 1) };
         to_where.push(is_bit_set);
     }
 }
@@ -160,4 +269,37 @@ mod test {
         let expanded_board = compact_to_board(&mut compact_repr).unwrap();
         assert_eq!(b, expanded_board);
     }
+
+    #[test]
+    fn test_missing_king() {
+        let b = Board::from_ascii_board_fen(b"8/8/8/8/8/8/8/4K3").unwrap();
+        assert!(matches!(
+            try_board_to_compact(&b),
+            Err(CompactError::KingCount {
+                color: Black,
+                count: 0
+            })
+        ));
+        assert!(try_board_to_compact(&Board::new()).is_ok());
+    }
+
+    #[test]
+    fn test_square_occupied_twice() {
+        // Both kings on a1.
+        let compact_repr = [0u8, 0, 0];
+        assert!(compact_slice_to_board(&compact_repr).is_ok());
+        assert!(matches!(
+            try_compact_slice_to_board(&compact_repr, false),
+            Err(CompactError::SquareOccupiedTwice(Square::A1))
+        ));
+    }
+
+    #[test]
+    fn test_truncated_board() {
+        let compact_repr = board_to_compact(&Board::new());
+        assert!(matches!(
+            try_compact_slice_to_board(&compact_repr[..10], false),
+            Err(CompactError::Bits(_))
+        ));
+    }
 }
@@ -0,0 +1,188 @@
+//! Compact encoding of a full game position.
+//!
+//! The layout starts with exactly the same bits as [`crate::board_to_compact`],
+//! without the padding, and continues with the state that the board alone does not capture:
+//!
+//! - 1 bit for the side to move (0 is White, 1 is Black);
+//! - 4 bits of castling rights: White king-side, White queen-side,
+//!   Black king-side, Black queen-side;
+//! - 1 bit showing whether there is an en passant square,
+//!   followed by its 3-bit file if there is one
+//!   (the rank follows from the side to move);
+//! - 1 bit showing whether the clocks are present,
+//!   followed by 16 bits of halfmove clock and 16 bits of fullmove number if they are.
+//!
+//! Only en passant squares where the capture is actually legal are stored,
+//! so positions that only differ in an unusable en passant square get the same encoding.
+
+use alloc::vec::Vec;
+use core::num::NonZeroU32;
+
+use bitvec::prelude::Msb0;
+use bitvec::vec::BitVec;
+use shakmaty::{
+    Bitboard, CastlingMode, Chess, Color, EnPassantMode, File, FromSetup, Position, Rank, Setup,
+    Square,
+};
+
+use crate::{push_board, push_tail_bits, try_compact_to_board, CompactError};
+
+/// The rook squares of the castling rights, in the order they are written.
+pub(crate) const CASTLING_ROOKS: [Square; 4] = [Square::H1, Square::A1, Square::H8, Square::A8];
+
+/// Encodes the position, optionally including the halfmove clock and fullmove number.
+///
+/// Clocks larger than 16 bits are saturated.
+pub fn position_to_compact(pos: &Chess, with_clocks: bool) -> Vec<u8> {
+    let mut output: BitVec<u8, Msb0> = BitVec::with_capacity(288);
+    push_board(&mut output, pos.board());
+    push_position_state(&mut output, pos, with_clocks);
+
+    output.set_uninitialized(true);
+    output.into_vec()
+}
+
+/// Appends everything except the board to `output`, without padding.
+pub(crate) fn push_position_state(output: &mut BitVec<u8, Msb0>, pos: &Chess, with_clocks: bool) {
+    let mut push = |value, count| {
+        push_tail_bits(output, value, count);
+    };
+
+    push(pos.turn().fold_wb(0, 1), 1);
+
+    let castling_rights = pos.castles().castling_rights();
+    for rook in CASTLING_ROOKS {
+        push(castling_rights.contains(rook) as u8, 1);
+    }
+
+    match pos.ep_square(EnPassantMode::Legal) {
+        Some(ep) => {
+            push(1, 1);
+            push(ep.file() as u8, 3);
+        }
+        None => push(0, 1),
+    }
+
+    if with_clocks {
+        push(1, 1);
+        for clock in [pos.halfmoves(), pos.fullmoves().get()] {
+            let clock = clock.min(u16::MAX as u32) as u16;
+            push((clock >> 8) as u8, 8);
+            push(clock as u8, 8);
+        }
+    } else {
+        push(0, 1);
+    }
+}
+
+pub fn compact_to_position(r: &mut bitreader::BitReader) -> Result<Chess, CompactError> {
+    let board = try_compact_to_board(r, false)?;
+    let setup = read_position_state(r, board)?;
+    Ok(Chess::from_setup(setup, CastlingMode::Standard)?)
+}
+
+/// Reads everything after the board, and combines it with the board into a setup.
+pub(crate) fn read_position_state(
+    r: &mut bitreader::BitReader,
+    board: shakmaty::Board,
+) -> Result<Setup, CompactError> {
+    let turn = if r.read_bool()? {
+        Color::Black
+    } else {
+        Color::White
+    };
+
+    let mut castling_rights = Bitboard::EMPTY;
+    for rook in CASTLING_ROOKS {
+        if r.read_bool()? {
+            castling_rights |= Bitboard::from_square(rook);
+        }
+    }
+
+    let ep_square = if r.read_bool()? {
+        let file = File::new(r.read_u8(3)? as u32);
+        // The en passant square is behind the pawn that has just moved,
+        // so it is on the opponent's third rank.
+        let rank = turn.fold_wb(Rank::Sixth, Rank::Third);
+        Some(Square::from_coords(file, rank))
+    } else {
+        None
+    };
+
+    let (halfmoves, fullmoves) = if r.read_bool()? {
+        let halfmoves = r.read_u16(16)? as u32;
+        let fullmoves = r.read_u16(16)? as u32;
+        (
+            halfmoves,
+            NonZeroU32::new(fullmoves).unwrap_or(NonZeroU32::MIN),
+        )
+    } else {
+        (0, NonZeroU32::MIN)
+    };
+
+    Ok(Setup {
+        board,
+        turn,
+        castling_rights,
+        ep_square,
+        halfmoves,
+        fullmoves,
+        ..Setup::empty()
+    })
+}
+
+pub fn compact_slice_to_position(r: &[u8]) -> Result<Chess, CompactError> {
+    compact_to_position(&mut bitreader::BitReader::new(r))
+}
+
+#[cfg(test)]
+mod test {
+    use shakmaty::fen::Fen;
+
+    use super::*;
+
+    fn round_trip(fen: &str, with_clocks: bool) -> Chess {
+        let pos: Chess = Fen::from_ascii(fen.as_bytes())
+            .unwrap()
+            .into_position(CastlingMode::Standard)
+            .unwrap();
+        let compact_repr = position_to_compact(&pos, with_clocks);
+        compact_slice_to_position(&compact_repr).unwrap()
+    }
+
+    fn fen_of(pos: Chess) -> String {
+        Fen::from_position(pos, EnPassantMode::Legal).to_string()
+    }
+
+    #[test]
+    fn test_position_round_trip() {
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "rnbqkbnr/pp1ppppp/8/2p5/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2",
+            "r3k2r/8/8/8/8/8/8/R3K2R w Kq - 13 40",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            "8/8/8/8/3pP3/8/8/k6K b - e3 0 50",
+        ] {
+            assert_eq!(fen_of(round_trip(fen, true)), fen);
+        }
+    }
+
+    #[test]
+    fn test_position_without_clocks() {
+        let pos = round_trip("r3k2r/8/8/8/8/8/8/R3K2R b Qk - 13 40", false);
+        assert_eq!(fen_of(pos), "r3k2r/8/8/8/8/8/8/R3K2R b Qk - 0 1");
+    }
+
+    #[test]
+    fn test_position_starts_with_board() {
+        let pos = Chess::default();
+        let board_repr = crate::board_to_compact(pos.board());
+        let mut r = bitreader::BitReader::new(&board_repr);
+        let position_repr = position_to_compact(&pos, false);
+        let mut pr = bitreader::BitReader::new(&position_repr);
+        assert_eq!(
+            crate::compact_to_board(&mut r).unwrap(),
+            crate::compact_to_board(&mut pr).unwrap()
+        );
+    }
+}
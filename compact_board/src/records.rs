@@ -0,0 +1,529 @@
+//! Streaming record files of counted positions.
+//!
+//! Unlike a serialized trie, a record file can be written and read one record at a time,
+//! so it never has to fit in memory, and the index at its end allows seeking to a key.
+//!
+//! The file consists of:
+//!
+//! - the header: the magic bytes `CBRF` and the record format version byte;
+//! - the records, in strictly increasing byte order of their keys:
+//!   the key length as a varint, the key bytes, then the count as a varint.
+//!   A key length of 0 ends the records, so keys can never be empty,
+//!   and keys are at most [`MAX_KEY_LEN`] bytes long;
+//! - the index: the number of entries as a varint, then for every entry the key length,
+//!   key bytes and byte offset of the record in the file, all as varints.
+//!   Every [`INDEX_INTERVAL`]th record has an entry;
+//! - the trailer: the byte offset of the index and the number of records,
+//!   both as little-endian `u64`, followed by the magic bytes `CBRI`.
+//!
+//! The varints are LEB128: 7 bits per byte, least significant first,
+//! with the top bit set on every byte except the last.
+//!
+//! Unlike the values in the board tries, where 0 means "seen once",
+//! the count of a record is the actual number of times the key was seen.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const HEADER_MAGIC: &[u8; 4] = b"CBRF";
+const TRAILER_MAGIC: &[u8; 4] = b"CBRI";
+const RECORD_FORMAT_VERSION: u8 = 1;
+const HEADER_LEN: u64 = 5;
+const TRAILER_LEN: i64 = 20;
+
+/// How many records there are between consecutive index entries.
+pub const INDEX_INTERVAL: u64 = 1024;
+
+/// The longest key a record can have, which is far longer than any board encoding,
+/// so that a corrupt key length is caught before it is allocated.
+pub const MAX_KEY_LEN: usize = 64;
+
+fn write_varint(w: &mut impl Write, mut value: u64) -> io::Result<usize> {
+    let mut buf = [0u8; 10];
+    let mut len = 0;
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf[len] = byte;
+            len += 1;
+            break;
+        }
+        buf[len] = byte | 0x80;
+        len += 1;
+    }
+    w.write_all(&buf[..len])?;
+    Ok(len)
+}
+
+fn read_varint(r: &mut impl Read) -> io::Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0u8];
+        r.read_exact(&mut byte)?;
+        // The last byte only has room for the top bit of the value.
+        if shift == 63 && byte[0] & 0x7e != 0 {
+            return Err(invalid_data("varint is longer than 64 bits"));
+        }
+        value |= ((byte[0] & 0x7f) as u64) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid_data("varint is longer than 64 bits"))
+}
+
+fn read_key(r: &mut impl Read, len: u64) -> io::Result<Vec<u8>> {
+    if len > MAX_KEY_LEN as u64 {
+        return Err(invalid_data("record key is longer than the longest key"));
+    }
+    let mut key = vec![0; len as usize];
+    r.read_exact(&mut key)?;
+    Ok(key)
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// Writes records to a file.
+///
+/// The keys must be pushed in strictly increasing order,
+/// and [`RecordWriter::finish`] must be called to write the index.
+/// Wrapping the writer in a [`std::io::BufWriter`] is recommended.
+pub struct RecordWriter<W: Write> {
+    inner: W,
+    offset: u64,
+    len: u64,
+    last_key: Option<Vec<u8>>,
+    index: Vec<(Vec<u8>, u64)>,
+}
+
+impl<W: Write> RecordWriter<W> {
+    pub fn new(mut inner: W) -> io::Result<RecordWriter<W>> {
+        inner.write_all(HEADER_MAGIC)?;
+        inner.write_all(&[RECORD_FORMAT_VERSION])?;
+        Ok(RecordWriter {
+            inner,
+            offset: HEADER_LEN,
+            len: 0,
+            last_key: None,
+            index: vec![],
+        })
+    }
+
+    pub fn push(&mut self, key: &[u8], count: u64) -> io::Result<()> {
+        if key.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "record keys cannot be empty",
+            ));
+        }
+        if key.len() > MAX_KEY_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("record keys cannot be longer than {MAX_KEY_LEN} bytes"),
+            ));
+        }
+        if let Some(last_key) = &self.last_key {
+            if key <= last_key.as_slice() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "record keys must be pushed in strictly increasing order",
+                ));
+            }
+        }
+
+        if self.len.is_multiple_of(INDEX_INTERVAL) {
+            self.index.push((key.to_vec(), self.offset));
+        }
+        self.offset += write_varint(&mut self.inner, key.len() as u64)? as u64;
+        self.inner.write_all(key)?;
+        self.offset += key.len() as u64;
+        self.offset += write_varint(&mut self.inner, count)? as u64;
+        self.len += 1;
+        self.last_key = Some(key.to_vec());
+        Ok(())
+    }
+
+    /// How many records have been pushed so far.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Writes the index and trailer, and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        write_varint(&mut self.inner, 0)?;
+        let index_offset = self.offset + 1;
+        write_varint(&mut self.inner, self.index.len() as u64)?;
+        for (key, offset) in self.index.iter() {
+            write_varint(&mut self.inner, key.len() as u64)?;
+            self.inner.write_all(key)?;
+            write_varint(&mut self.inner, *offset)?;
+        }
+        self.inner.write_all(&index_offset.to_le_bytes())?;
+        self.inner.write_all(&self.len.to_le_bytes())?;
+        self.inner.write_all(TRAILER_MAGIC)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+/// Writes the counts of a board trie as a record file, and returns the number of records.
+pub fn write_trie_as_records<W: Write>(
+    trie: &radix_trie::Trie<Vec<u8>, usize>,
+    out: W,
+) -> io::Result<u64> {
+    use radix_trie::TrieCommon;
+
+    let mut writer = RecordWriter::new(out)?;
+    // The trie iterates in the byte order of the keys, which is the order of the records.
+    for (board, count) in trie.iter() {
+        // In the trie 0 means "seen once", in the records it is the actual count.
+        writer.push(board, *count as u64 + 1)?;
+    }
+    let len = writer.len();
+    writer.finish()?;
+    Ok(len)
+}
+
+/// Reads the records of a file in order, as `(key, count)` pairs.
+///
+/// Only the records are read, so this works on streams that cannot seek.
+/// Wrapping the reader in a [`std::io::BufReader`] is recommended.
+pub struct RecordReader<R: Read> {
+    inner: R,
+    finished: bool,
+}
+
+impl<R: Read> RecordReader<R> {
+    pub fn new(mut inner: R) -> io::Result<RecordReader<R>> {
+        let mut header = [0u8; HEADER_LEN as usize];
+        inner.read_exact(&mut header)?;
+        if &header[..4] != HEADER_MAGIC {
+            return Err(invalid_data("not a record file"));
+        }
+        if header[4] != RECORD_FORMAT_VERSION {
+            return Err(invalid_data("unknown record file version"));
+        }
+        Ok(RecordReader::without_header(inner))
+    }
+
+    /// Continues reading records from a reader placed at the start of a record.
+    fn without_header(inner: R) -> RecordReader<R> {
+        RecordReader {
+            inner,
+            finished: false,
+        }
+    }
+
+    fn read_record(&mut self) -> io::Result<Option<(Vec<u8>, u64)>> {
+        let len = read_varint(&mut self.inner)?;
+        if len == 0 {
+            return Ok(None);
+        }
+        let key = read_key(&mut self.inner, len)?;
+        let count = read_varint(&mut self.inner)?;
+        Ok(Some((key, count)))
+    }
+}
+
+impl<R: Read> Iterator for RecordReader<R> {
+    type Item = io::Result<(Vec<u8>, u64)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        let record = self.read_record();
+        if !matches!(record, Ok(Some(_))) {
+            self.finished = true;
+        }
+        record.transpose()
+    }
+}
+
+/// Reads a record file using its index, so that lookups do not need to read the whole file.
+pub struct IndexedRecordReader<R: Read + Seek> {
+    inner: R,
+    index: Vec<(Vec<u8>, u64)>,
+    len: u64,
+}
+
+impl<R: Read + Seek> IndexedRecordReader<R> {
+    pub fn open(mut inner: R) -> io::Result<IndexedRecordReader<R>> {
+        inner.seek(SeekFrom::Start(0))?;
+        RecordReader::new(&mut inner)?;
+
+        inner.seek(SeekFrom::End(-TRAILER_LEN))?;
+        let mut trailer = [0u8; TRAILER_LEN as usize];
+        inner.read_exact(&mut trailer)?;
+        if &trailer[16..] != TRAILER_MAGIC {
+            return Err(invalid_data(
+                "record file has no index, it may be incomplete",
+            ));
+        }
+        let index_offset = u64::from_le_bytes(trailer[..8].try_into().unwrap());
+        let len = u64::from_le_bytes(trailer[8..16].try_into().unwrap());
+
+        inner.seek(SeekFrom::Start(index_offset))?;
+        let mut index_reader = io::BufReader::new(&mut inner);
+        let entries = read_varint(&mut index_reader)?;
+        if entries > len / INDEX_INTERVAL + 1 {
+            return Err(invalid_data(
+                "record index has more entries than there are records",
+            ));
+        }
+        let mut index = Vec::with_capacity(entries as usize);
+        for _ in 0..entries {
+            let key_len = read_varint(&mut index_reader)?;
+            let key = read_key(&mut index_reader, key_len)?;
+            let offset = read_varint(&mut index_reader)?;
+            index.push((key, offset));
+        }
+
+        Ok(IndexedRecordReader { inner, index, len })
+    }
+
+    /// The number of records in the file.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Iterates over the records whose keys are equal to or greater than `key`.
+    pub fn records_from(
+        &mut self,
+        key: &[u8],
+    ) -> io::Result<impl Iterator<Item = io::Result<(Vec<u8>, u64)>> + '_> {
+        let entry = self.index.partition_point(|(k, _)| k.as_slice() <= key);
+        let offset = match entry {
+            0 => HEADER_LEN,
+            entry => self.index[entry - 1].1,
+        };
+        self.inner.seek(SeekFrom::Start(offset))?;
+        let key = key.to_vec();
+        Ok(
+            RecordReader::without_header(io::BufReader::new(&mut self.inner))
+                .skip_while(move |record| matches!(record, Ok((k, _)) if *k < key)),
+        )
+    }
+
+    /// Finds the count of a single key.
+    pub fn get(&mut self, key: &[u8]) -> io::Result<Option<u64>> {
+        match self.records_from(key)?.next() {
+            Some(Ok((k, count))) if k == key => Ok(Some(count)),
+            Some(Err(e)) => Err(e),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Checks that the record file at `path` is complete and has `expected` records,
+/// from its trailer, without reading the records.
+pub fn check_record_count(path: &Path, expected: u64) -> io::Result<()> {
+    let reader = IndexedRecordReader::open(std::fs::File::open(path)?)?;
+    if reader.len() != expected {
+        return Err(invalid_data(&format!(
+            "{} has {} records, but {expected} were written",
+            path.display(),
+            reader.len()
+        )));
+    }
+    Ok(())
+}
+
+/// Merges several streams of records, each sorted like a record file, into one sorted stream.
+///
+/// The counts of a key that is in several of the streams are added up,
+/// so merging the record files of different months gives the counts over all of them.
+/// Only one record of every stream is held in memory at a time.
+pub struct MergedRecords<I> {
+    inputs: Vec<I>,
+    /// The next record of every stream that is not finished, with the index of the stream.
+    heap: BinaryHeap<Reverse<(Vec<u8>, usize, u64)>>,
+    failed: bool,
+}
+
+impl<I: Iterator<Item = io::Result<(Vec<u8>, u64)>>> MergedRecords<I> {
+    pub fn new(inputs: impl IntoIterator<Item = I>) -> io::Result<MergedRecords<I>> {
+        let mut merged = MergedRecords {
+            inputs: inputs.into_iter().collect(),
+            heap: BinaryHeap::new(),
+            failed: false,
+        };
+        for input in 0..merged.inputs.len() {
+            merged.refill(input)?;
+        }
+        Ok(merged)
+    }
+
+    /// Reads the next record of the input into the heap, if it has one.
+    fn refill(&mut self, input: usize) -> io::Result<()> {
+        if let Some(record) = self.inputs[input].next() {
+            let (key, count) = record?;
+            self.heap.push(Reverse((key, input, count)));
+        }
+        Ok(())
+    }
+}
+
+impl<I: Iterator<Item = io::Result<(Vec<u8>, u64)>>> Iterator for MergedRecords<I> {
+    type Item = io::Result<(Vec<u8>, u64)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let Reverse((key, input, mut count)) = self.heap.pop()?;
+        let mut result = self.refill(input);
+        while let Some(Reverse((next_key, next_input, next_count))) = self.heap.peek() {
+            if *next_key != key || result.is_err() {
+                break;
+            }
+            count += next_count;
+            let next_input = *next_input;
+            self.heap.pop();
+            result = self.refill(next_input);
+        }
+        match result {
+            Ok(()) => Some(Ok((key, count))),
+            Err(e) => {
+                self.failed = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn key(n: u64) -> Vec<u8> {
+        // Big-endian, so that the byte order matches the numeric order.
+        n.to_be_bytes().to_vec()
+    }
+
+    fn write_file(count: u64) -> Vec<u8> {
+        let mut writer = RecordWriter::new(vec![]).unwrap();
+        for n in 0..count {
+            writer.push(&key(n * 2), n * 1000).unwrap();
+        }
+        assert_eq!(writer.len(), count);
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn test_varint_round_trip() {
+        for value in [0, 1, 127, 128, 300, u32::MAX as u64, u64::MAX] {
+            let mut buf = vec![];
+            write_varint(&mut buf, value).unwrap();
+            assert_eq!(read_varint(&mut Cursor::new(buf)).unwrap(), value);
+        }
+        // Nine full bytes and a tenth with more than the top bit.
+        let mut too_long = vec![0xff; 9];
+        too_long.push(0x02);
+        assert!(read_varint(&mut Cursor::new(too_long)).is_err());
+    }
+
+    #[test]
+    fn test_corrupt_key_length() {
+        let mut file = HEADER_MAGIC.to_vec();
+        file.push(RECORD_FORMAT_VERSION);
+        write_varint(&mut file, 1 << 40).unwrap();
+        let mut reader = RecordReader::new(Cursor::new(file)).unwrap();
+        assert!(reader.next().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_stream_round_trip() {
+        let file = write_file(3000);
+        let records: Vec<_> = RecordReader::new(Cursor::new(file))
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(records.len(), 3000);
+        for (n, (k, count)) in records.into_iter().enumerate() {
+            assert_eq!(k, key(n as u64 * 2));
+            assert_eq!(count, n as u64 * 1000);
+        }
+    }
+
+    #[test]
+    fn test_indexed_lookup() {
+        let mut reader = IndexedRecordReader::open(Cursor::new(write_file(3000))).unwrap();
+        assert_eq!(reader.len(), 3000);
+        assert_eq!(reader.get(&key(0)).unwrap(), Some(0));
+        assert_eq!(reader.get(&key(2048)).unwrap(), Some(1024 * 1000));
+        assert_eq!(reader.get(&key(5998)).unwrap(), Some(2999 * 1000));
+        assert_eq!(reader.get(&key(2049)).unwrap(), None);
+        assert_eq!(reader.get(&key(6000)).unwrap(), None);
+
+        let (first, _) = reader
+            .records_from(&key(4001))
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(first, key(4002));
+        assert_eq!(reader.records_from(&key(0)).unwrap().count(), 3000);
+    }
+
+    #[test]
+    fn test_empty_file() {
+        let file = write_file(0);
+        assert_eq!(
+            RecordReader::new(Cursor::new(file.clone()))
+                .unwrap()
+                .count(),
+            0
+        );
+        let mut reader = IndexedRecordReader::open(Cursor::new(file)).unwrap();
+        assert!(reader.is_empty());
+        assert_eq!(reader.get(&key(1)).unwrap(), None);
+    }
+
+    #[test]
+    fn test_merge() {
+        let first = write_file(3000);
+        let mut writer = RecordWriter::new(vec![]).unwrap();
+        writer.push(&key(1), 5).unwrap();
+        writer.push(&key(2), 7).unwrap();
+        writer.push(&key(7000), 9).unwrap();
+        let second = writer.finish().unwrap();
+
+        let inputs = [first, second].map(|file| RecordReader::new(Cursor::new(file)).unwrap());
+        let merged: Vec<_> = MergedRecords::new(inputs)
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(merged.len(), 3002);
+        assert_eq!(merged[0], (key(0), 0));
+        assert_eq!(merged[1], (key(1), 5));
+        assert_eq!(merged[2], (key(2), 1000 + 7));
+        assert_eq!(merged[3001], (key(7000), 9));
+        assert!(merged.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    }
+
+    #[test]
+    fn test_unsorted_keys() {
+        let mut writer = RecordWriter::new(vec![]).unwrap();
+        writer.push(&key(2), 1).unwrap();
+        assert!(writer.push(&key(2), 1).is_err());
+        assert!(writer.push(&key(1), 1).is_err());
+        assert!(writer.push(&[], 1).is_err());
+        assert!(writer.push(&[0xff; MAX_KEY_LEN + 1], 1).is_err());
+    }
+}
@@ -0,0 +1,151 @@
+//! What happened after a position in the games that reached it, like in an opening explorer.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use serde::{Deserialize, Serialize};
+
+use crate::labels::{GameResult, LabelRecord};
+
+/// The most moves kept for a position. Merging drops the rest,
+/// so that the stats of a position stay small however many games reached it.
+pub const MAX_MOVES: usize = 16;
+
+/// The results, ratings and moves of the games that reached a position.
+///
+/// A position that is reached twice in the same game is counted twice.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PositionStats {
+    /// How many times the position was reached, including in games without a result.
+    pub games: u32,
+    pub white_wins: u32,
+    pub draws: u32,
+    pub black_wins: u32,
+    /// The sum of the average ratings of the games where both ratings are known,
+    /// and the number of those games.
+    pub rating_sum: u64,
+    pub rated_games: u32,
+    /// The [`MAX_MOVES`] most common moves played in the position in UCI notation,
+    /// and how often each was played, with the most common first.
+    ///
+    /// A move that was dropped while it was rare is counted again from 0 if it comes back,
+    /// so the counts of the least common moves can be too low.
+    pub moves: Vec<(String, u32)>,
+}
+
+impl PositionStats {
+    /// The stats of a single time the position was reached.
+    pub fn from_label(label: &LabelRecord) -> PositionStats {
+        let mut stats = PositionStats {
+            games: 1,
+            moves: alloc::vec![(label.uci.clone(), 1)],
+            ..Default::default()
+        };
+        match label.result {
+            GameResult::WhiteWins => stats.white_wins = 1,
+            GameResult::BlackWins => stats.black_wins = 1,
+            GameResult::Draw => stats.draws = 1,
+            GameResult::Unknown => {}
+        }
+        if let (Some(white), Some(black)) = (label.white_elo, label.black_elo) {
+            stats.rating_sum = (white as u64 + black as u64) / 2;
+            stats.rated_games = 1;
+        }
+        stats
+    }
+
+    /// Adds the games counted in `other`, which must be for the same position.
+    pub fn merge(&mut self, other: &PositionStats) {
+        self.games = self.games.saturating_add(other.games);
+        self.white_wins = self.white_wins.saturating_add(other.white_wins);
+        self.draws = self.draws.saturating_add(other.draws);
+        self.black_wins = self.black_wins.saturating_add(other.black_wins);
+        self.rating_sum = self.rating_sum.saturating_add(other.rating_sum);
+        self.rated_games = self.rated_games.saturating_add(other.rated_games);
+        for (uci, times) in other.moves.iter() {
+            match self.moves.iter_mut().find(|(m, _)| m == uci) {
+                Some((_, count)) => *count = count.saturating_add(*times),
+                None => self.moves.push((uci.clone(), *times)),
+            }
+        }
+        // Ties are broken by the move, so the order does not depend on the order of merging.
+        self.moves
+            .sort_by(|(a, a_times), (b, b_times)| b_times.cmp(a_times).then_with(|| a.cmp(b)));
+        self.moves.truncate(MAX_MOVES);
+    }
+
+    /// The average rating of the rated games, if there were any.
+    pub fn average_rating(&self) -> Option<f32> {
+        (self.rated_games > 0).then(|| self.rating_sum as f32 / self.rated_games as f32)
+    }
+
+    /// The average result for White, from 1 for always winning to -1 for always losing,
+    /// over the games with a known result.
+    pub fn white_score(&self) -> Option<f32> {
+        let decided = self.white_wins + self.draws + self.black_wins;
+        (decided > 0).then(|| (self.white_wins as f32 - self.black_wins as f32) / decided as f32)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn label(uci: &str, result: GameResult, elos: Option<(u16, u16)>) -> LabelRecord {
+        LabelRecord {
+            position: alloc::vec![],
+            uci: uci.into(),
+            result,
+            white_elo: elos.map(|e| e.0),
+            black_elo: elos.map(|e| e.1),
+            ply: 0,
+        }
+    }
+
+    #[test]
+    fn test_merge_stats() {
+        let mut stats = PositionStats::from_label(&label("e2e4", GameResult::WhiteWins, None));
+        stats.merge(&PositionStats::from_label(&label(
+            "d2d4",
+            GameResult::Draw,
+            Some((1500, 1700)),
+        )));
+        stats.merge(&PositionStats::from_label(&label(
+            "d2d4",
+            GameResult::Unknown,
+            Some((2000, 2000)),
+        )));
+
+        assert_eq!(stats.games, 3);
+        assert_eq!((stats.white_wins, stats.draws, stats.black_wins), (1, 1, 0));
+        assert_eq!(stats.average_rating(), Some(1800.0));
+        assert_eq!(stats.white_score(), Some(0.5));
+        assert_eq!(
+            stats.moves,
+            [("d2d4".to_string(), 2), ("e2e4".to_string(), 1)]
+        );
+    }
+
+    #[test]
+    fn test_only_the_most_common_moves_are_kept() {
+        let mut stats = PositionStats::from_label(&label("a2a3", GameResult::Draw, None));
+        stats.merge(&PositionStats::from_label(&label(
+            "a2a3",
+            GameResult::Draw,
+            None,
+        )));
+        for file in "abcdefghijklmnopqrst".chars() {
+            let uci = alloc::format!("{file}7{file}6");
+            stats.merge(&PositionStats::from_label(&label(
+                &uci,
+                GameResult::Draw,
+                None,
+            )));
+        }
+
+        assert_eq!(stats.games, 22);
+        assert_eq!(stats.moves.len(), MAX_MOVES);
+        assert_eq!(stats.moves[0], ("a2a3".to_string(), 2));
+        assert_eq!(stats.moves[1], ("a7a6".to_string(), 1));
+    }
+}
@@ -0,0 +1,260 @@
+//! Canonical representatives of symmetric positions.
+//!
+//! Swapping the colours of all pieces, flipping the board vertically and passing the move
+//! to the other side gives a position that is the same game with the colours exchanged.
+//! Mirroring the board left to right also gives an equivalent position,
+//! but only when nobody can castle any more, since castling is not symmetric.
+//!
+//! Counting and labelling work on the canonical representative of each class of
+//! equivalent positions. The [`Transform`] that produced it is kept,
+//! so that a move chosen in the canonical position can be translated back to the real one.
+
+use shakmaty::{
+    uci::Uci, Bitboard, Board, ByColor, CastlingMode, Chess, Color, EnPassantMode, FromSetup,
+    Piece, Position, Role, Setup, Square,
+};
+
+use crate::{board_to_compact, position_to_compact, CompactError};
+
+/// A combination of the symmetries of a position.
+///
+/// Every transform is its own inverse,
+/// so undoing a transform means applying it once more.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Transform {
+    /// Colours are swapped, the board is flipped vertically and the other side is to move.
+    pub color_flip: bool,
+    /// The board is mirrored left to right.
+    pub mirror: bool,
+}
+
+impl Transform {
+    pub const IDENTITY: Transform = Transform {
+        color_flip: false,
+        mirror: false,
+    };
+
+    pub fn inverse(self) -> Transform {
+        self
+    }
+
+    pub fn square(self, sq: Square) -> Square {
+        let sq = if self.color_flip {
+            sq.flip_vertical()
+        } else {
+            sq
+        };
+        if self.mirror {
+            sq.flip_horizontal()
+        } else {
+            sq
+        }
+    }
+
+    fn bitboard(self, bb: Bitboard) -> Bitboard {
+        let bb = if self.color_flip {
+            bb.flip_vertical()
+        } else {
+            bb
+        };
+        if self.mirror {
+            bb.flip_horizontal()
+        } else {
+            bb
+        }
+    }
+
+    pub fn board(self, board: &Board) -> Board {
+        let mut output = Board::empty();
+        for color in [Color::White, Color::Black] {
+            for role in Role::ALL {
+                let new_color = if self.color_flip {
+                    color.other()
+                } else {
+                    color
+                };
+                for sq in board.by_piece(Piece { color, role }) {
+                    output.set_piece_at(
+                        self.square(sq),
+                        Piece {
+                            color: new_color,
+                            role,
+                        },
+                    );
+                }
+            }
+        }
+        output
+    }
+
+    /// Transforms everything about the setup except the move counters.
+    pub fn setup(self, setup: &Setup) -> Setup {
+        let mut output = setup.clone();
+        output.board = self.board(&setup.board);
+        output.promoted = self.bitboard(setup.promoted);
+        output.castling_rights = self.bitboard(setup.castling_rights);
+        output.ep_square = setup.ep_square.map(|sq| self.square(sq));
+        if self.color_flip {
+            output.turn = setup.turn.other();
+            output.pockets = setup.pockets.map(|p| ByColor {
+                white: p.black,
+                black: p.white,
+            });
+            output.remaining_checks = setup.remaining_checks.map(|c| ByColor {
+                white: c.black,
+                black: c.white,
+            });
+        }
+        output
+    }
+
+    /// Transforms the position.
+    ///
+    /// This fails if the transform mirrors a position where castling is still possible.
+    pub fn position(self, pos: &Chess) -> Result<Chess, CompactError> {
+        let setup = self.setup(&pos.clone().into_setup(EnPassantMode::Legal));
+        Ok(Chess::from_setup(setup, CastlingMode::Standard)?)
+    }
+
+    pub fn uci(self, m: &Uci) -> Uci {
+        match m {
+            Uci::Normal {
+                from,
+                to,
+                promotion,
+            } => Uci::Normal {
+                from: self.square(*from),
+                to: self.square(*to),
+                promotion: *promotion,
+            },
+            Uci::Put { role, to } => Uci::Put {
+                role: *role,
+                to: self.square(*to),
+            },
+            Uci::Null => Uci::Null,
+        }
+    }
+}
+
+/// Finds the canonical representative of the position:
+/// the one with White to move and, if nobody can castle,
+/// the smaller encoding out of the position and its mirror image.
+///
+/// The returned transform turns the given position into the canonical one, and back.
+pub fn canonicalize_position(pos: &Chess) -> (Chess, Transform) {
+    let mut transform = Transform {
+        color_flip: pos.turn() == Color::Black,
+        mirror: false,
+    };
+    let mut canonical = transform
+        .position(pos)
+        .expect("swapping colours keeps a position legal");
+
+    if canonical.castles().castling_rights().is_empty() {
+        let mirror = Transform {
+            mirror: true,
+            ..Transform::IDENTITY
+        };
+        let mirrored = mirror
+            .position(&canonical)
+            .expect("mirroring keeps a position without castling rights legal");
+        if position_to_compact(&mirrored, false) < position_to_compact(&canonical, false) {
+            canonical = mirrored;
+            transform.mirror = true;
+        }
+    }
+
+    (canonical, transform)
+}
+
+/// Finds the canonical representative of the board:
+/// the smaller encoding out of the board and its colour-flipped version.
+///
+/// A bare board does not say whether castling is possible, so it is never mirrored.
+pub fn canonicalize_board(board: &Board) -> (Board, Transform) {
+    let flip = Transform {
+        color_flip: true,
+        mirror: false,
+    };
+    let flipped = flip.board(board);
+    if board_to_compact(&flipped) < board_to_compact(board) {
+        (flipped, flip)
+    } else {
+        (board.clone(), Transform::IDENTITY)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use shakmaty::fen::Fen;
+
+    use super::*;
+
+    fn parse(fen: &str) -> Chess {
+        Fen::from_ascii(fen.as_bytes())
+            .unwrap()
+            .into_position(CastlingMode::Standard)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_color_flip_is_canonical() {
+        let white = parse("rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2");
+        let black = parse("rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 2");
+        let (white_canonical, white_transform) = canonicalize_position(&white);
+        let (black_canonical, black_transform) = canonicalize_position(&black);
+        assert_eq!(white_transform, Transform::IDENTITY);
+        assert!(black_transform.color_flip);
+        assert!(!black_transform.mirror);
+        assert_eq!(white_canonical.board(), black_canonical.board());
+        assert_eq!(black_canonical.turn(), Color::White);
+    }
+
+    #[test]
+    fn test_mirror_only_without_castling() {
+        let left = parse("4k3/8/8/8/8/8/1P6/4K3 w - - 0 1");
+        let right = parse("3k4/8/8/8/8/8/6P1/3K4 w - - 0 1");
+        let (left_canonical, left_transform) = canonicalize_position(&left);
+        let (right_canonical, right_transform) = canonicalize_position(&right);
+        assert_ne!(left_transform.mirror, right_transform.mirror);
+        assert_eq!(left_canonical.board(), right_canonical.board());
+
+        let castling = parse("4k3/8/8/8/8/8/1P6/4K2R w K - 0 1");
+        assert!(!canonicalize_position(&castling).1.mirror);
+    }
+
+    #[test]
+    fn test_transform_undo() {
+        let pos = parse("8/5k2/8/8/2N5/8/8/1K6 b - - 0 40");
+        let (canonical, transform) = canonicalize_position(&pos);
+        let restored = transform.inverse().position(&canonical).unwrap();
+        assert_eq!(restored.board(), pos.board());
+        assert_eq!(restored.turn(), pos.turn());
+
+        let m = Uci::from_ascii(b"f7e6").unwrap();
+        let canonical_move = transform.uci(&m);
+        assert!(canonical_move.to_move(&canonical).is_ok());
+        assert_eq!(transform.inverse().uci(&canonical_move), m);
+    }
+
+    #[test]
+    fn test_board_canonical() {
+        let b = Board::new();
+        let (canonical, transform) = canonicalize_board(&b);
+        assert_eq!(canonical, b);
+        assert_eq!(transform, Transform::IDENTITY);
+
+        let asymmetric =
+            Board::from_ascii_board_fen(b"rnbqkbnr/pp1ppppp/8/2p5/4P3/8/PPPP1PPP/RNBQKBNR")
+                .unwrap();
+        let (a, _) = canonicalize_board(&asymmetric);
+        let (b, _) = canonicalize_board(
+            &Transform {
+                color_flip: true,
+                mirror: false,
+            }
+            .board(&asymmetric),
+        );
+        assert_eq!(a, b);
+    }
+}
@@ -0,0 +1,51 @@
+use shakmaty::{Bitboard, Board, Color, Role};
+
+use crate::CompactError;
+
+/// Checks that the board could appear in a game of standard chess:
+/// no pawns on the first or last rank, and no more than 16 pieces per side.
+///
+/// Any board produced from a legal game passes this,
+/// so a failure on decoded data means that the data is corrupted.
+pub fn validate_board(board: &Board) -> Result<(), CompactError> {
+    let back_ranks = Bitboard::BACKRANKS;
+    if let Some(square) = (board.by_role(Role::Pawn) & back_ranks).first() {
+        return Err(CompactError::PawnOnBackRank(square));
+    }
+
+    for color in [Color::White, Color::Black] {
+        let count = board.by_color(color).count();
+        if count > 16 {
+            return Err(CompactError::TooManyPieces { color, count });
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use shakmaty::Square;
+
+    use super::*;
+
+    #[test]
+    fn test_validate_board() {
+        assert!(validate_board(&Board::new()).is_ok());
+
+        let b = Board::from_ascii_board_fen(b"4k2P/8/8/8/8/8/8/4K3").unwrap();
+        assert!(matches!(
+            validate_board(&b),
+            Err(CompactError::PawnOnBackRank(Square::H8))
+        ));
+
+        let b = Board::from_ascii_board_fen(b"4k3/8/8/8/8/QQQQQQQQ/PPPPPPPP/4K3").unwrap();
+        assert!(matches!(
+            validate_board(&b),
+            Err(CompactError::TooManyPieces {
+                color: Color::White,
+                count: 17
+            })
+        ));
+    }
+}
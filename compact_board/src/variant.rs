@@ -0,0 +1,273 @@
+//! Compact encoding of positions from chess variants.
+//!
+//! This works on a [`Setup`], so it can store anything shakmaty can model for variants
+//! that have exactly one king per side: Chess960 castling with any rook files,
+//! Crazyhouse pockets and promoted pieces, and Three-check counters.
+//! Which variant the position belongs to is not stored,
+//! since all the positions in a file usually come from the same variant.
+//!
+//! The layout starts with the bits of [`crate::board_to_compact`], without the padding, and continues with:
+//!
+//! - 1 bit for the side to move (0 is White, 1 is Black);
+//! - the castling rights of White, then Black: each right is a 1 bit followed by
+//!   the 3-bit file of its rook, and the list of each side ends with a 0 bit;
+//! - 1 bit showing whether there is an en passant square,
+//!   followed by its 3-bit file if there is one;
+//! - 1 bit showing whether promoted pieces are tracked, and if they are,
+//!   a 1 bit followed by the 6-bit square for each promoted piece, ending with a 0 bit;
+//! - 1 bit showing whether there are pockets, and if there are,
+//!   5 bits with the number of pawns, knights, bishops, rooks and queens
+//!   in the pocket of White, then Black;
+//! - 1 bit showing whether there are check counters, and if there are,
+//!   2 bits with the remaining checks of White, then Black;
+//! - 1 bit showing whether the clocks are present,
+//!   followed by 16 bits of halfmove clock and 16 bits of fullmove number if they are.
+
+use alloc::vec::Vec;
+use core::num::NonZeroU32;
+
+use bitvec::prelude::Msb0;
+use bitvec::vec::BitVec;
+use shakmaty::{
+    Bitboard, ByColor, ByRole, Color, File, Piece, Rank, RemainingChecks, Role, Setup, Square,
+};
+
+use crate::{push_board, push_tail_bits, try_compact_to_board, CompactError};
+
+const POCKET_ROLES: [Role; 5] = [
+    Role::Pawn,
+    Role::Knight,
+    Role::Bishop,
+    Role::Rook,
+    Role::Queen,
+];
+
+/// Encodes the setup, optionally including the halfmove clock and fullmove number.
+///
+/// This fails if a side does not have exactly one king.
+/// Clocks larger than 16 bits and pockets with more than 31 pieces of a kind are saturated.
+pub fn setup_to_compact(setup: &Setup, with_clocks: bool) -> Result<Vec<u8>, CompactError> {
+    for color in [Color::White, Color::Black] {
+        let count = setup
+            .board
+            .by_piece(Piece {
+                color,
+                role: Role::King,
+            })
+            .count();
+        if count != 1 {
+            return Err(CompactError::KingCount { color, count });
+        }
+    }
+
+    let mut output: BitVec<u8, Msb0> = BitVec::with_capacity(320);
+    push_board(&mut output, &setup.board);
+    let mut push = |value, count| {
+        push_tail_bits(&mut output, value, count);
+    };
+
+    push(setup.turn.fold_wb(0, 1), 1);
+
+    for color in [Color::White, Color::Black] {
+        let back_rank = Bitboard::from_rank(color.fold_wb(Rank::First, Rank::Eighth));
+        for rook in setup.castling_rights & back_rank {
+            push(1, 1);
+            push(rook.file() as u8, 3);
+        }
+        push(0, 1);
+    }
+
+    match setup.ep_square {
+        Some(ep) => {
+            push(1, 1);
+            push(ep.file() as u8, 3);
+        }
+        None => push(0, 1),
+    }
+
+    // Promoted pieces are only tracked when there are pockets.
+    if setup.pockets.is_some() || setup.promoted.any() {
+        push(1, 1);
+        for sq in setup.promoted {
+            push(1, 1);
+            push(sq as u8, 6);
+        }
+        push(0, 1);
+    } else {
+        push(0, 1);
+    }
+
+    match &setup.pockets {
+        Some(pockets) => {
+            push(1, 1);
+            for pocket in [&pockets.white, &pockets.black] {
+                for role in POCKET_ROLES {
+                    push((*pocket.get(role)).min(31), 5);
+                }
+            }
+        }
+        None => push(0, 1),
+    }
+
+    match &setup.remaining_checks {
+        Some(checks) => {
+            push(1, 1);
+            for remaining in [checks.white, checks.black] {
+                push(u32::from(remaining) as u8, 2);
+            }
+        }
+        None => push(0, 1),
+    }
+
+    if with_clocks {
+        push(1, 1);
+        for clock in [setup.halfmoves, setup.fullmoves.get()] {
+            let clock = clock.min(u16::MAX as u32) as u16;
+            push((clock >> 8) as u8, 8);
+            push(clock as u8, 8);
+        }
+    } else {
+        push(0, 1);
+    }
+
+    output.set_uninitialized(true);
+    Ok(output.into_vec())
+}
+
+pub fn compact_to_setup(r: &mut bitreader::BitReader) -> Result<Setup, CompactError> {
+    let board = try_compact_to_board(r, false)?;
+
+    let turn = if r.read_bool()? {
+        Color::Black
+    } else {
+        Color::White
+    };
+
+    let mut castling_rights = Bitboard::EMPTY;
+    for color in [Color::White, Color::Black] {
+        while r.read_bool()? {
+            let file = File::new(r.read_u8(3)? as u32);
+            let rank = color.fold_wb(Rank::First, Rank::Eighth);
+            castling_rights |= Bitboard::from_square(Square::from_coords(file, rank));
+        }
+    }
+
+    let ep_square = if r.read_bool()? {
+        let file = File::new(r.read_u8(3)? as u32);
+        let rank = turn.fold_wb(Rank::Sixth, Rank::Third);
+        Some(Square::from_coords(file, rank))
+    } else {
+        None
+    };
+
+    let mut promoted = Bitboard::EMPTY;
+    if r.read_bool()? {
+        while r.read_bool()? {
+            promoted |= Bitboard::from_square(Square::new(r.read_u8(6)? as u32));
+        }
+    }
+
+    let pockets = if r.read_bool()? {
+        let mut read_pocket = || -> Result<ByRole<u8>, CompactError> {
+            let mut pocket = ByRole {
+                pawn: 0,
+                knight: 0,
+                bishop: 0,
+                rook: 0,
+                queen: 0,
+                king: 0,
+            };
+            for role in POCKET_ROLES {
+                *pocket.get_mut(role) = r.read_u8(5)?;
+            }
+            Ok(pocket)
+        };
+        let white = read_pocket()?;
+        let black = read_pocket()?;
+        Some(ByColor { white, black })
+    } else {
+        None
+    };
+
+    let remaining_checks = if r.read_bool()? {
+        let white = RemainingChecks::new(r.read_u8(2)?.min(3) as u32);
+        let black = RemainingChecks::new(r.read_u8(2)?.min(3) as u32);
+        Some(ByColor { white, black })
+    } else {
+        None
+    };
+
+    let (halfmoves, fullmoves) = if r.read_bool()? {
+        let halfmoves = r.read_u16(16)? as u32;
+        let fullmoves = r.read_u16(16)? as u32;
+        (
+            halfmoves,
+            NonZeroU32::new(fullmoves).unwrap_or(NonZeroU32::MIN),
+        )
+    } else {
+        (0, NonZeroU32::MIN)
+    };
+
+    Ok(Setup {
+        board,
+        promoted,
+        pockets,
+        turn,
+        castling_rights,
+        ep_square,
+        remaining_checks,
+        halfmoves,
+        fullmoves,
+    })
+}
+
+pub fn compact_slice_to_setup(r: &[u8]) -> Result<Setup, CompactError> {
+    compact_to_setup(&mut bitreader::BitReader::new(r))
+}
+
+#[cfg(test)]
+mod test {
+    use shakmaty::{fen::Fen, CastlingMode, Chess, FromSetup, Position};
+
+    use super::*;
+
+    fn round_trip(fen: &str) -> (Setup, Setup) {
+        let setup = Fen::from_ascii(fen.as_bytes()).unwrap().into_setup();
+        let compact_repr = setup_to_compact(&setup, true).unwrap();
+        (setup, compact_slice_to_setup(&compact_repr).unwrap())
+    }
+
+    #[test]
+    fn test_chess960_round_trip() {
+        let (setup, decoded) =
+            round_trip("bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9");
+        assert_eq!(setup, decoded);
+        let pos = Chess::from_setup(decoded, CastlingMode::Chess960).unwrap();
+        assert_eq!(pos.castles().castling_rights(), setup.castling_rights);
+    }
+
+    #[test]
+    fn test_crazyhouse_round_trip() {
+        let (setup, decoded) =
+            round_trip("r1bk3r/ppp2ppp/2n5/4p3/4P3/8/PPP2PPP/R3KB~NR[QNbpp] b KQ - 0 12");
+        assert!(setup.pockets.is_some());
+        assert!(setup.promoted.any());
+        assert_eq!(setup, decoded);
+    }
+
+    #[test]
+    fn test_three_check_round_trip() {
+        let (setup, decoded) =
+            round_trip("rnbqkbnr/ppp2ppp/8/3pp3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq d6 0 3 +1+2");
+        assert!(setup.remaining_checks.is_some());
+        assert_eq!(setup, decoded);
+    }
+
+    #[test]
+    fn test_standard_setup() {
+        let (setup, decoded) =
+            round_trip("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1");
+        assert_eq!(setup, decoded);
+        assert!(setup_to_compact(&Setup::empty(), false).is_err());
+    }
+}
@@ -0,0 +1,66 @@
+//! JavaScript bindings, so the frontend can show the positions stored in datasets and tries.
+//!
+//! Boards and positions cross the boundary as FEN strings,
+//! and compact data as `Uint8Array`s.
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use shakmaty::{fen::Fen, Bitboard, Board, Chess, EnPassantMode};
+use wasm_bindgen::prelude::*;
+
+use crate::{CompactBoard, CompactError, FormatVersion};
+
+fn js_error(e: CompactError) -> JsError {
+    JsError::new(&e.to_string())
+}
+
+fn board_fen(board: &Board) -> String {
+    board.board_fen(Bitboard::EMPTY).to_string()
+}
+
+fn position_fen(pos: Chess) -> String {
+    Fen::from_position(pos, EnPassantMode::Legal).to_string()
+}
+
+/// Encodes the board part of a FEN with the unversioned layout.
+#[wasm_bindgen(js_name = encodeBoard)]
+pub fn encode_board(fen: &str) -> Result<Vec<u8>, JsError> {
+    let board: CompactBoard = fen.parse().map_err(js_error)?;
+    Ok(board.into_bytes())
+}
+
+/// Decodes a board with the unversioned layout, such as a trie key or a batch entry,
+/// into the board part of a FEN.
+#[wasm_bindgen(js_name = decodeBoard)]
+pub fn decode_board(data: &[u8]) -> Result<String, JsError> {
+    let board = crate::try_compact_slice_to_board(data, false).map_err(js_error)?;
+    Ok(board_fen(&board))
+}
+
+/// Decodes a position with the unversioned layout into a full FEN.
+#[wasm_bindgen(js_name = decodePosition)]
+pub fn decode_position(data: &[u8]) -> Result<String, JsError> {
+    let pos = crate::compact_slice_to_position(data).map_err(js_error)?;
+    Ok(position_fen(pos))
+}
+
+/// The version byte of versioned compact data.
+#[wasm_bindgen(js_name = formatVersion)]
+pub fn format_version(data: &[u8]) -> Result<u8, JsError> {
+    crate::format_version(data)
+        .map(FormatVersion::tag)
+        .map_err(js_error)
+}
+
+#[wasm_bindgen(js_name = decodeVersionedBoard)]
+pub fn decode_versioned_board(data: &[u8]) -> Result<String, JsError> {
+    let board = crate::versioned_compact_to_board(data).map_err(js_error)?;
+    Ok(board_fen(&board))
+}
+
+#[wasm_bindgen(js_name = decodeVersionedPosition)]
+pub fn decode_versioned_position(data: &[u8]) -> Result<String, JsError> {
+    let pos = crate::versioned_compact_to_position(data).map_err(js_error)?;
+    Ok(position_fen(pos))
+}
@@ -0,0 +1,214 @@
+//! Writing files so that a crash never leaves a partly written file under the final name.
+//!
+//! The file is written under a temporary name next to the final one, synced to disk,
+//! read back to compare its checksum with the bytes that were written,
+//! optionally checked further by the caller, and only then renamed to the final name.
+//! Inputs that the new file replaces should only be deleted after that.
+
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use radix_trie::{Trie, TrieCommon, TrieKey};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::file_crc32;
+
+/// The writer given to the closure of [`write_atomically`],
+/// which keeps the CRC-32 of everything written to it.
+pub struct AtomicWriter {
+    inner: BufWriter<File>,
+    hasher: crc32fast::Hasher,
+}
+
+impl Write for AtomicWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// The path that a file is written to before it is renamed to `path`.
+fn temp_path(path: &Path) -> PathBuf {
+    let mut temp_path = OsString::from(path.as_os_str());
+    temp_path.push(".tmp");
+    temp_path.into()
+}
+
+/// Writes a file with `write`, and returns what it returned and the CRC-32 of the file.
+pub fn write_atomically<T>(
+    path: &Path,
+    write: impl FnOnce(&mut AtomicWriter) -> io::Result<T>,
+) -> io::Result<(T, u32)> {
+    write_checked(path, write, |_, _| Ok(()))
+}
+
+/// Like [`write_atomically`], but also calls `check` with the complete temporary file
+/// and what `write` returned, like the number of entries, before renaming it.
+/// If the check fails, the temporary file is deleted and the error is returned.
+pub fn write_checked<T>(
+    path: &Path,
+    write: impl FnOnce(&mut AtomicWriter) -> io::Result<T>,
+    check: impl FnOnce(&Path, &T) -> io::Result<()>,
+) -> io::Result<(T, u32)> {
+    let temp_path = temp_path(path);
+    let result = write_and_check(&temp_path, write, check);
+    let (output, crc32) = match result {
+        Ok(written) => written,
+        Err(e) => {
+            let _ = std::fs::remove_file(&temp_path);
+            return Err(e);
+        }
+    };
+
+    std::fs::rename(&temp_path, path)?;
+    // The rename itself is only durable once the directory is synced.
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+    File::open(dir.unwrap_or(Path::new(".")))?.sync_all()?;
+    Ok((output, crc32))
+}
+
+fn write_and_check<T>(
+    temp_path: &Path,
+    write: impl FnOnce(&mut AtomicWriter) -> io::Result<T>,
+    check: impl FnOnce(&Path, &T) -> io::Result<()>,
+) -> io::Result<(T, u32)> {
+    let mut writer = AtomicWriter {
+        inner: BufWriter::new(File::create(temp_path)?),
+        hasher: crc32fast::Hasher::new(),
+    };
+    let output = write(&mut writer)?;
+    writer.flush()?;
+    let expected = writer.hasher.finalize();
+    writer.inner.into_inner()?.sync_all()?;
+
+    let actual = file_crc32(temp_path)?;
+    if actual != expected {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} reads back with CRC-32 {actual:08x}, but {expected:08x} was written",
+                temp_path.display()
+            ),
+        ));
+    }
+    check(temp_path, &output)?;
+    Ok((output, actual))
+}
+
+/// Writes `value` with postcard, and returns the CRC-32 of the file.
+pub fn write_postcard_atomically<T: Serialize + ?Sized>(path: &Path, value: &T) -> io::Result<u32> {
+    let ((), crc32) = write_atomically(path, |out| write_postcard(value, out))?;
+    Ok(crc32)
+}
+
+/// Like [`write_postcard_atomically`], but also reads the file back with postcard
+/// before renaming it, and checks that it has as many entries as `value`.
+pub fn write_postcard_checked<T: Entries>(path: &Path, value: &T) -> io::Result<u32> {
+    let write = |out: &mut AtomicWriter| {
+        write_postcard(value, out)?;
+        Ok(value.entries())
+    };
+    let check = |temp_path: &Path, expected: &usize| {
+        let mut buf = vec![0; 32 * 1024];
+        let reader = BufReader::new(File::open(temp_path)?);
+        let (read, _): (T, _) = postcard::from_io((reader, &mut buf)).map_err(io::Error::other)?;
+        if read.entries() != *expected {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} reads back with {} entries, but {expected} were written",
+                    temp_path.display(),
+                    read.entries()
+                ),
+            ));
+        }
+        Ok(())
+    };
+    let (_, crc32) = write_checked(path, write, check)?;
+    Ok(crc32)
+}
+
+fn write_postcard<T: Serialize + ?Sized>(value: &T, out: &mut AtomicWriter) -> io::Result<()> {
+    postcard::to_io(value, out).map_err(io::Error::other)?;
+    Ok(())
+}
+
+/// A collection written with postcard, whose entries [`write_postcard_checked`] counts.
+pub trait Entries: Serialize + DeserializeOwned {
+    fn entries(&self) -> usize;
+}
+
+impl<K, V> Entries for Trie<K, V>
+where
+    K: TrieKey + Clone + Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+{
+    fn entries(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> Entries for Vec<T> {
+    fn entries(&self) -> usize {
+        self.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_write_atomically() {
+        let dir = std::env::temp_dir().join(format!("atomic-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("file");
+        std::fs::write(&path, b"old").unwrap();
+
+        // A failed check leaves the old file alone.
+        let result = write_checked(
+            &path,
+            |w| w.write_all(b"new contents"),
+            |_, _| Err(io::Error::new(io::ErrorKind::InvalidData, "wrong")),
+        );
+        assert!(result.is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), b"old");
+        assert!(!temp_path(&path).exists());
+
+        let (len, crc32) = write_atomically(&path, |w| {
+            w.write_all(b"new contents")?;
+            Ok(12)
+        })
+        .unwrap();
+        assert_eq!(len, 12);
+        assert_eq!(std::fs::read(&path).unwrap(), b"new contents");
+        assert_eq!(crc32, file_crc32(&path).unwrap());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_write_postcard_checked() {
+        let dir = std::env::temp_dir().join(format!("postcard-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("trie");
+        let mut trie = Trie::new();
+        trie.insert(vec![1u8, 2], 3usize);
+        trie.insert(vec![1u8], 1usize);
+
+        let crc32 = write_postcard_checked(&path, &trie).unwrap();
+        assert_eq!(crc32, file_crc32(&path).unwrap());
+        let bytes = std::fs::read(&path).unwrap();
+        let read: Trie<Vec<u8>, usize> = postcard::from_bytes(&bytes).unwrap();
+        assert_eq!(read.len(), 2);
+        assert_eq!(read.get(&vec![1, 2]), Some(&3));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
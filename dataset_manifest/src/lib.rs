@@ -0,0 +1,393 @@
+//! The catalogue of a data directory, kept as `manifest.json` in it.
+//!
+//! Every trie, record file and batch directory that the tools write is recorded
+//! with the months of games it covers, how its games and positions were selected,
+//! the layout of its keys, its number of entries and a checksum,
+//! so that nothing has to be worked out from the file names.
+//! The months of an artifact do not have to be consecutive.
+//!
+//! A data directory from before the manifest is catalogued from the names of its files,
+//! like `single-2016-6-board-trie.postcard` or `combined-2016-6+2016-7-board-tries.postcard`,
+//! the first time its manifest is loaded.
+//!
+//! The tools write their files, and the manifest itself, with [`write_atomically`].
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Read};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+mod atomic;
+mod month;
+
+pub use atomic::{
+    write_atomically, write_checked, write_postcard_atomically, write_postcard_checked,
+    AtomicWriter, Entries,
+};
+pub use month::Month;
+
+pub const MANIFEST_NAME: &str = "manifest.json";
+
+/// What an artifact holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ArtifactKind {
+    /// A postcard-serialized trie of board counts, where 0 means "seen once".
+    BoardTrie,
+    /// A record file of board counts, from `compact_board::records`.
+    BoardCounts,
+    /// A postcard-serialized trie of `compact_board::PositionStats`.
+    PositionStats,
+    /// A directory of label batches.
+    Labels,
+    /// A directory of evaluation batches.
+    Evals,
+}
+
+/// A file or directory in the data directory.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Artifact {
+    pub kind: ArtifactKind,
+    /// The months of the games it was made from. Empty if they are not known,
+    /// like for games read from local files.
+    pub months: BTreeSet<Month>,
+    /// How the games and positions were selected, as the extractor options that decide it,
+    /// or null if every position of every game was counted,
+    /// which the files catalogued from their names are taken to be.
+    pub filter: serde_json::Value,
+    /// The `compact_board::FormatVersion` tag of the layout of the boards in it.
+    pub encoding_version: u8,
+    /// The keys seen fewer times than this were dropped, or 1 if none were.
+    /// Board tries catalogued from their names by [`Manifest::load`] have 2,
+    /// since the extractor always dropped the boards seen once before there was a manifest.
+    pub min_count: u64,
+    /// The number of keys in a trie or record file, or of batches in a batch directory,
+    /// if it is known.
+    pub entries: Option<u64>,
+    /// The CRC-32 of a file, if it is known. Directories have none.
+    pub crc32: Option<u32>,
+}
+
+impl Artifact {
+    /// Whether the two artifacts can be merged into one:
+    /// they have to be of the same kind, be selected and encoded the same way
+    /// and be trimmed at the same count, but for different months.
+    pub fn can_merge_with(&self, other: &Artifact) -> bool {
+        self.kind == other.kind
+            && self.filter == other.filter
+            && self.encoding_version == other.encoding_version
+            && self.min_count == other.min_count
+            && !self.months.is_empty()
+            && !other.months.is_empty()
+            && self.months.is_disjoint(&other.months)
+    }
+
+    /// The artifact made by merging the two, without its entries and checksum.
+    pub fn merged_with(&self, other: &Artifact) -> Artifact {
+        Artifact {
+            months: self.months.union(&other.months).copied().collect(),
+            entries: None,
+            crc32: None,
+            ..self.clone()
+        }
+    }
+
+    /// Checks the checksum of the file at `path`, if there is one.
+    pub fn verify(&self, path: &Path) -> io::Result<()> {
+        let Some(expected) = self.crc32 else {
+            return Ok(());
+        };
+        let actual = file_crc32(path)?;
+        if actual != expected {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "the CRC-32 of {} is {actual:08x}, but the manifest says {expected:08x}",
+                    path.display()
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// All the artifacts in a data directory, by their path relative to it.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub artifacts: BTreeMap<String, Artifact>,
+}
+
+impl Manifest {
+    /// Reads the manifest of the directory,
+    /// or catalogues its files from their names if it has no manifest yet.
+    pub fn load(dir: &Path) -> io::Result<Manifest> {
+        match std::fs::File::open(dir.join(MANIFEST_NAME)) {
+            Ok(file) => Ok(serde_json::from_reader(io::BufReader::new(file))?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Manifest::from_names(dir),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Writes the manifest to the directory, replacing the old one only once it is complete.
+    pub fn save(&self, dir: &Path) -> io::Result<()> {
+        write_atomically(&dir.join(MANIFEST_NAME), |w| {
+            Ok(serde_json::to_writer_pretty(w, self)?)
+        })?;
+        Ok(())
+    }
+
+    /// Loads the manifest of the directory, changes it and saves it again.
+    pub fn update(dir: &Path, change: impl FnOnce(&mut Manifest)) -> io::Result<()> {
+        let mut manifest = Manifest::load(dir)?;
+        change(&mut manifest);
+        manifest.save(dir)
+    }
+
+    pub fn of_kind(&self, kind: ArtifactKind) -> impl Iterator<Item = (&String, &Artifact)> {
+        self.artifacts
+            .iter()
+            .filter(move |(_, artifact)| artifact.kind == kind)
+    }
+
+    /// The name of an artifact of the kind that includes the month.
+    pub fn covering(&self, kind: ArtifactKind, month: Month) -> Option<&str> {
+        self.of_kind(kind)
+            .find(|(_, artifact)| artifact.months.contains(&month))
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Catalogues the tries and record files of a directory from their names.
+    /// Their entries and checksums are not known.
+    /// The board tries are taken to be trimmed of the boards seen once,
+    /// like the extractor did before the manifest.
+    fn from_names(dir: &Path) -> io::Result<Manifest> {
+        let mut manifest = Manifest::default();
+        for file in std::fs::read_dir(dir)? {
+            let name = file?.file_name().to_string_lossy().to_string();
+            let Some((kind, months)) = parse_legacy_name(&name) else {
+                continue;
+            };
+            let artifact = Artifact {
+                kind,
+                months,
+                filter: serde_json::Value::Null,
+                encoding_version: 1,
+                min_count: if kind == ArtifactKind::BoardTrie {
+                    2
+                } else {
+                    1
+                },
+                entries: None,
+                crc32: None,
+            };
+            manifest.artifacts.insert(name, artifact);
+        }
+        Ok(manifest)
+    }
+}
+
+/// The kind and months of a file named like the tools named them before the manifest.
+fn parse_legacy_name(name: &str) -> Option<(ArtifactKind, BTreeSet<Month>)> {
+    const SUFFIXES: [(&str, ArtifactKind); 4] = [
+        ("-board-trie.postcard", ArtifactKind::BoardTrie),
+        ("-board-tries.postcard", ArtifactKind::BoardTrie),
+        ("-board-counts.records", ArtifactKind::BoardCounts),
+        ("-position-stats.postcard", ArtifactKind::PositionStats),
+    ];
+    let (stem, kind) = SUFFIXES
+        .iter()
+        .find_map(|(suffix, kind)| Some((name.strip_suffix(suffix)?, *kind)))?;
+    if let Some(month) = stem.strip_prefix("single-") {
+        return Some((kind, BTreeSet::from([month.parse().ok()?])));
+    }
+    let (first, last) = stem.strip_prefix("combined-")?.split_once('+')?;
+    let (first, last): (Month, Month) = (first.parse().ok()?, last.parse().ok()?);
+    Some((kind, first.range_to(last).into_iter().collect()))
+}
+
+/// The name of a file merged from the months, like `combined-2016-06+2016-08_2016-11<suffix>`
+/// for June to August and November, so that different sets of months get different names.
+pub fn combined_name(months: &BTreeSet<Month>, suffix: &str) -> String {
+    let mut runs: Vec<(Month, Month)> = vec![];
+    for month in months.iter().copied() {
+        match runs.last_mut() {
+            Some((_, last)) if last.next() == month => *last = month,
+            _ => runs.push((month, month)),
+        }
+    }
+    let runs: Vec<String> = runs
+        .into_iter()
+        .map(|(first, last)| {
+            if first == last {
+                first.to_string()
+            } else {
+                format!("{first}+{last}")
+            }
+        })
+        .collect();
+    format!("combined-{}{suffix}", runs.join("_"))
+}
+
+/// The end of the names of board tries with keys in the `compact_board::FormatVersion`
+/// with this tag, and of several months if `combined`.
+///
+/// Only V1 tries are named `-board-trie.postcard` or `-board-tries.postcard`,
+/// like before the manifest, so that reading board tries by their names only ever finds V1 keys.
+pub fn board_trie_suffix(encoding_version: u8, combined: bool) -> String {
+    match (encoding_version, combined) {
+        (1, false) => "-board-trie.postcard".to_string(),
+        (1, true) => "-board-tries.postcard".to_string(),
+        (version, _) => format!("-board-keys-v{version}.postcard"),
+    }
+}
+
+/// The CRC-32 of the contents of a file, read in chunks.
+pub fn file_crc32(path: &Path) -> io::Result<u32> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buf = vec![0; 1 << 20];
+    loop {
+        let len = file.read(&mut buf)?;
+        if len == 0 {
+            return Ok(hasher.finalize());
+        }
+        hasher.update(&buf[..len]);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn month(s: &str) -> Month {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_legacy_names() {
+        assert_eq!(
+            parse_legacy_name("single-2016-6-board-trie.postcard"),
+            Some((ArtifactKind::BoardTrie, BTreeSet::from([month("2016-06")])))
+        );
+        let (kind, months) =
+            parse_legacy_name("combined-2016-11+2017-2-board-tries.postcard").unwrap();
+        assert_eq!(kind, ArtifactKind::BoardTrie);
+        assert_eq!(
+            months,
+            month("2016-11")
+                .range_to(month("2017-02"))
+                .into_iter()
+                .collect()
+        );
+        assert_eq!(
+            parse_legacy_name("single-2016-6-board-trie.postcard.meta.json"),
+            None
+        );
+        assert_eq!(parse_legacy_name("manifest.json"), None);
+    }
+
+    #[test]
+    fn test_legacy_tries_are_trimmed() {
+        let dir = std::env::temp_dir().join(format!("legacy-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("single-2016-6-board-trie.postcard"), b"").unwrap();
+        std::fs::write(dir.join("single-2016-6-board-counts.records"), b"").unwrap();
+        let manifest = Manifest::load(&dir).unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+
+        let min_count = |name: &str| manifest.artifacts[name].min_count;
+        assert_eq!(min_count("single-2016-6-board-trie.postcard"), 2);
+        assert_eq!(min_count("single-2016-6-board-counts.records"), 1);
+    }
+
+    #[test]
+    fn test_merge_legacy_with_new() {
+        let dir = std::env::temp_dir().join(format!("legacy-merge-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("single-2016-6-board-trie.postcard"), b"").unwrap();
+        let manifest = Manifest::load(&dir).unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+        let legacy = &manifest.artifacts["single-2016-6-board-trie.postcard"];
+
+        // As the extractor records a trie of every game with the default options.
+        let new = Artifact {
+            kind: ArtifactKind::BoardTrie,
+            months: BTreeSet::from([month("2016-07")]),
+            filter: serde_json::Value::Null,
+            encoding_version: 1,
+            min_count: 2,
+            entries: Some(10),
+            crc32: Some(1),
+        };
+        assert!(legacy.can_merge_with(&new));
+        let merged = legacy.merged_with(&new);
+        assert_eq!(merged.months.len(), 2);
+        assert_eq!(merged.min_count, 2);
+    }
+
+    #[test]
+    fn test_board_trie_suffix() {
+        let name = format!("single-2016-6{}", board_trie_suffix(1, false));
+        assert_eq!(parse_legacy_name(&name).unwrap().0, ArtifactKind::BoardTrie);
+        assert_eq!(board_trie_suffix(2, true), "-board-keys-v2.postcard");
+        assert!(!board_trie_suffix(2, false).contains("board-trie"));
+    }
+
+    #[test]
+    fn test_combined_name() {
+        let months = [
+            "2016-06", "2016-07", "2016-08", "2016-11", "2017-01", "2017-02",
+        ];
+        let months = months.iter().map(|m| month(m)).collect();
+        assert_eq!(
+            combined_name(&months, "-board-tries.postcard"),
+            "combined-2016-06+2016-08_2016-11_2017-01+2017-02-board-tries.postcard"
+        );
+        // Names of consecutive months can still be read without a manifest.
+        let months = BTreeSet::from([month("2016-11"), month("2016-12"), month("2017-01")]);
+        let name = combined_name(&months, "-board-counts.records");
+        assert_eq!(
+            parse_legacy_name(&name),
+            Some((ArtifactKind::BoardCounts, months))
+        );
+    }
+
+    #[test]
+    fn test_merge_and_round_trip() {
+        let artifact = |months: &[&str]| Artifact {
+            kind: ArtifactKind::BoardTrie,
+            months: months.iter().map(|m| month(m)).collect(),
+            filter: serde_json::json!({"min_elo": 2000}),
+            encoding_version: 1,
+            min_count: 2,
+            entries: Some(10),
+            crc32: Some(1),
+        };
+        let (january, march) = (artifact(&["2016-01"]), artifact(&["2016-03"]));
+        assert!(january.can_merge_with(&march));
+        assert!(!january.can_merge_with(&artifact(&["2016-01", "2016-02"])));
+        let untrimmed_march = Artifact {
+            min_count: 1,
+            ..march.clone()
+        };
+        assert!(!january.can_merge_with(&untrimmed_march));
+        let merged = january.merged_with(&march);
+        assert_eq!(merged.months.len(), 2);
+        assert_eq!(merged.crc32, None);
+
+        let mut manifest = Manifest::default();
+        manifest.artifacts.insert("merged".into(), merged);
+        let json = serde_json::to_string(&manifest).unwrap();
+        assert!(json.contains("\"2016-03\""));
+        let read: Manifest = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            read.covering(ArtifactKind::BoardTrie, month("2016-03")),
+            Some("merged")
+        );
+        assert_eq!(
+            read.covering(ArtifactKind::BoardTrie, month("2016-02")),
+            None
+        );
+    }
+}
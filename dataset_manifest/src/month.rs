@@ -0,0 +1,78 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// A calendar month, written as YYYY-MM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Month {
+    pub year: i32,
+    pub month: i32,
+}
+
+impl Month {
+    pub fn next(self) -> Month {
+        if self.month == 12 {
+            Month {
+                year: self.year + 1,
+                month: 1,
+            }
+        } else {
+            Month {
+                year: self.year,
+                month: self.month + 1,
+            }
+        }
+    }
+
+    /// All the months from `self` to `last`, both included.
+    pub fn range_to(self, last: Month) -> Vec<Month> {
+        let mut output = vec![];
+        let mut current = self;
+        while current <= last {
+            output.push(current);
+            current = current.next();
+        }
+        output
+    }
+}
+
+impl fmt::Display for Month {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{:0>2}", self.year, self.month)
+    }
+}
+
+impl FromStr for Month {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (year, month) = s
+            .split_once('-')
+            .ok_or_else(|| format!("expected YYYY-MM, got {s:?}"))?;
+        let year = year
+            .parse()
+            .map_err(|e| format!("invalid year {year:?}: {e}"))?;
+        let month = month
+            .parse()
+            .map_err(|e| format!("invalid month {month:?}: {e}"))?;
+        if !(1..=12).contains(&month) {
+            return Err(format!("month {month} is not between 1 and 12"));
+        }
+        Ok(Month { year, month })
+    }
+}
+
+/// Months are written as YYYY-MM strings in the manifest.
+impl Serialize for Month {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Month {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
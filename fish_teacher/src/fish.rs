@@ -5,6 +5,7 @@ use std::{
 };
 
 use anyhow::Result;
+use compact_board::evaluation::EngineEvaluation;
 use shakmaty::{fen::Fen, uci::Uci, Bitboard, Color, Position};
 
 pub struct Stockfish {
@@ -150,58 +151,3 @@ impl Stockfish {
         Ok(Some((eval, Uci::from_str(&parts[1].trim()).unwrap())))
     }
 }
-
-#[derive(Debug, Clone, Copy)]
-pub enum EngineEvaluation {
-    /// This amount of advantage to white
-    Centipawns(i64),
-    /// Mate in this number of moves
-    Mate(i64),
-}
-
-fn translate(value: f32, left_min: f32, left_max: f32, right_min: f32, right_max: f32) -> f32 {
-    let left_span = left_max - left_min;
-    let right_span = right_max - right_min;
-    let value_scaled = (value - left_min) / left_span;
-    right_min + (value_scaled * right_span)
-}
-
-impl EngineEvaluation {
-    pub fn to_numeric_score(&self) -> f32 {
-        match self {
-            EngineEvaluation::Centipawns(v) => {
-                // The range between -3000 and 3000 centipawns is mapped into -0.8 to 0.8
-                let v = (*v).clamp(-3000, 3000) as f32;
-                translate(v, -3000.0, 3000.0, -0.8, 0.8)
-            }
-            EngineEvaluation::Mate(t) => {
-                // The range between mate-in 25 and 1 is mapped to 0.8 to 1.
-                let inv_fac = translate((*t).clamp(1, 25) as f32, 1.0, 25.0, 0.0, 0.2);
-                (if t.is_negative() { -1.0 } else { 1.0 }) - inv_fac
-            }
-        }
-    }
-
-    pub fn from_numeric_score(v: f32) -> Self {
-        // If the value is between -0.8 and 0.8, then it's a centipawns between -3000 and 3000
-        if v.abs() < 0.8 {
-            Self::Centipawns(translate(v, -0.8, 0.8, -3000.0, 3000.0) as i64)
-        } else {
-            let fac = v.abs() - 0.8;
-            let inv_fac = 0.2 - fac;
-            Self::Mate(translate(inv_fac, 0.0, 0.2, 1.0, 25.0).ceil() as i64)
-        }
-    }
-}
-
-impl EngineEvaluation {
-    pub fn from_str(text: &str, to_move: Color) -> Self {
-        let parts: Vec<_> = text.trim().split(" ").collect();
-        let number: i64 = parts[1].parse().unwrap();
-        match parts[0] {
-            "cp" => Self::Centipawns(number * to_move.fold_wb(1, -1)),
-            "mate" => Self::Mate(number * to_move.fold_wb(1, -1)),
-            other => panic!("Unknown scoring: {other}"),
-        }
-    }
-}
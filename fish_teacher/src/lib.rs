@@ -1,3 +1,4 @@
 #![feature(buf_read_has_data_left)]
 pub mod fish;
-pub use fish::EngineEvaluation;
+pub use compact_board::batch;
+pub use compact_board::evaluation::EngineEvaluation;
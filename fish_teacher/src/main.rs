@@ -1,25 +1,35 @@
 #![feature(buf_read_has_data_left)]
 pub mod fish;
 
+use std::path::Path;
+
 use fish::Stockfish;
 use rand::{seq::SliceRandom, SeedableRng};
-use shakmaty::{Bitboard, Board, ByColor, Chess, Color, FromSetup, Position, Setup};
+use shakmaty::{Bitboard, Chess, Color, FromSetup, Position, Setup};
 
-use compact_board::{board_to_compact, compact_slice_to_board};
+use compact_board::{board_to_compact, CompactBoard, FormatVersion};
+use dataset_manifest::{write_postcard_checked, ArtifactKind, Manifest};
+use fish_teacher::batch::{white_perspective, BatchEntry};
 use radix_trie::TrieCommon;
 use tokio::sync::mpsc;
 
-pub use fish::EngineEvaluation;
+pub use compact_board::evaluation::EngineEvaluation;
 
 async fn fish_worker(
-    mut board_rx: mpsc::Receiver<Vec<u8>>,
-    eval_tx: mpsc::Sender<(Vec<u8>, f32, String)>,
+    mut board_rx: mpsc::Receiver<CompactBoard>,
+    eval_tx: mpsc::Sender<BatchEntry>,
 ) {
     tokio::task::spawn_blocking(move || {
         let mut fish = Stockfish::new();
         loop {
             let compact_board = board_rx.blocking_recv().unwrap();
-            let mut board = compact_slice_to_board(&compact_board).unwrap();
+            let board = match compact_board.try_to_board(true) {
+                Ok(board) => board,
+                Err(e) => {
+                    println!("Skipping corrupted board {compact_board:?}: {e}");
+                    continue;
+                }
+            };
 
             //println!("fen: {}", board.board_fen(Bitboard::EMPTY));
 
@@ -63,16 +73,7 @@ async fn fish_worker(
             if !checkmate {
                 // If it is not checkmate from Black's perspective:
                 // First, transform the board so that it's still White's perspective.
-                let mut board = board.clone();
-                board.rotate_180();
-                let (by_role, by_color) = board.into_bitboards();
-                let board = Board::from_bitboards(
-                    by_role,
-                    ByColor {
-                        black: by_color.white,
-                        white: by_color.black,
-                    },
-                );
+                let board = white_perspective(&board, Color::Black);
                 // Now evaluate it from the new White's, old Black's, perspective
                 let res = fish.evaluate_board(&board, Color::White);
                 if let Err(_) = res {
@@ -101,15 +102,14 @@ async fn fish_worker(
     .unwrap();
 }
 
-async fn board_loader(senders: Vec<mpsc::Sender<Vec<u8>>>) {
-    let mut names = vec![];
-    for file in std::fs::read_dir("../hugedata").unwrap() {
-        let file_name = file.unwrap().file_name();
-        let name = file_name.to_string_lossy().to_string();
-        if name.contains("board-trie") {
-            names.push(name);
-        }
-    }
+async fn board_loader(senders: Vec<mpsc::Sender<CompactBoard>>) {
+    // The boards are read as V1 keys, so the tries recoded to other layouts are skipped.
+    let manifest = Manifest::load(Path::new("../hugedata")).unwrap();
+    let names: Vec<String> = manifest
+        .of_kind(ArtifactKind::BoardTrie)
+        .filter(|(_, artifact)| artifact.encoding_version == FormatVersion::V1.tag())
+        .map(|(name, _)| name.clone())
+        .collect();
     let name_skips = [("combined-2016-6+2016-7-board-tries.postcard", 4074000)];
 
     for name in names {
@@ -147,14 +147,14 @@ async fn board_loader(senders: Vec<mpsc::Sender<Vec<u8>>>) {
             sender_cycle
                 .next()
                 .unwrap()
-                .send(board.clone())
+                .send(CompactBoard::from_bytes(board.clone()))
                 .await
                 .unwrap();
         }
     }
 }
 
-async fn board_saver(mut recv: mpsc::Receiver<(Vec<u8>, f32, String)>) {
+async fn board_saver(mut recv: mpsc::Receiver<BatchEntry>) {
     let batch_size = 8192;
     let mut batch_idx: usize = 945;
     let mut rng = rand::rngs::StdRng::from_seed(rand::random());
@@ -166,13 +166,8 @@ async fn board_saver(mut recv: mpsc::Receiver<(Vec<u8>, f32, String)>) {
             println!("Shuffling batch {batch_idx}");
             values.shuffle(&mut rng);
             println!("Saving batch {batch_idx}");
-            let file = std::fs::OpenOptions::new()
-                .create(true)
-                .write(true)
-                .open(format!("../hugedata/batches/batch_{batch_idx}.postcard"))
-                .unwrap();
-            let buf = std::io::BufWriter::new(file);
-            postcard::to_io(&values, buf).unwrap();
+            let path = format!("../hugedata/batches/batch_{batch_idx}.postcard");
+            write_postcard_checked(Path::new(&path), &values).unwrap();
             values.clear();
             batch_idx += 1;
         }
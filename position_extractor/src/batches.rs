@@ -0,0 +1,123 @@
+use std::io;
+use std::path::PathBuf;
+
+use compact_board::batch::BatchEntry;
+use compact_board::LabelRecord;
+use dataset_manifest::write_postcard_checked;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::visitor::GamePositions;
+
+/// How many entries go in one batch file, the same as in the evaluation batches.
+const BATCH_SIZE: usize = 8192;
+
+/// Writes entries in batches of [`BATCH_SIZE`],
+/// to `<prefix>_0.postcard`, `<prefix>_1.postcard` and so on in the directory.
+pub struct BatchWriter<T> {
+    dir: PathBuf,
+    prefix: &'static str,
+    pending: Vec<T>,
+    batches_written: usize,
+}
+
+impl<T: Serialize + DeserializeOwned> BatchWriter<T> {
+    pub fn new(dir: PathBuf, prefix: &'static str) -> io::Result<BatchWriter<T>> {
+        std::fs::create_dir_all(&dir)?;
+        Ok(BatchWriter {
+            dir,
+            prefix,
+            pending: Vec::with_capacity(BATCH_SIZE),
+            batches_written: 0,
+        })
+    }
+
+    /// Continues numbering the batches after the ones that were already written.
+    pub fn starting_at(mut self, batches_written: usize) -> BatchWriter<T> {
+        self.batches_written = batches_written;
+        self
+    }
+
+    pub fn add(&mut self, entries: impl IntoIterator<Item = T>) -> io::Result<()> {
+        for entry in entries {
+            self.pending.push(entry);
+            if self.pending.len() >= BATCH_SIZE {
+                self.write_batch()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the pending entries as a possibly smaller batch, and returns the number of batches.
+    pub fn flush(&mut self) -> io::Result<usize> {
+        if !self.pending.is_empty() {
+            self.write_batch()?;
+        }
+        Ok(self.batches_written)
+    }
+
+    /// Writes the last batch and returns the number of batches.
+    pub fn finish(mut self) -> io::Result<usize> {
+        self.flush()
+    }
+
+    fn write_batch(&mut self) -> io::Result<()> {
+        let path = self
+            .dir
+            .join(format!("{}_{}.postcard", self.prefix, self.batches_written));
+        write_postcard_checked(&path, &self.pending)?;
+        self.pending.clear();
+        self.batches_written += 1;
+        Ok(())
+    }
+}
+
+/// The per-position outputs that are written besides the board trie, if they were requested.
+#[derive(Default)]
+pub struct GameOutputs {
+    /// Moves played and game results, as [`LabelRecord`]s.
+    pub labels: Option<BatchWriter<LabelRecord>>,
+    /// Evaluations from the `[%eval]` comments of the games,
+    /// in the shape of the evaluation batches written by `fish_teacher`.
+    pub evals: Option<BatchWriter<BatchEntry>>,
+}
+
+impl GameOutputs {
+    pub fn add(&mut self, game: &mut GamePositions) -> io::Result<()> {
+        if let Some(labels) = &mut self.labels {
+            labels.add(game.labels.drain(..))?;
+        }
+        if let Some(evals) = &mut self.evals {
+            evals.add(game.evals.drain(..))?;
+        }
+        Ok(())
+    }
+
+    /// Writes all pending entries, for a checkpoint,
+    /// and returns the number of label and evaluation batches.
+    pub fn flush(&mut self) -> io::Result<(usize, usize)> {
+        let labels = match &mut self.labels {
+            Some(labels) => labels.flush()?,
+            None => 0,
+        };
+        let evals = match &mut self.evals {
+            Some(evals) => evals.flush()?,
+            None => 0,
+        };
+        Ok((labels, evals))
+    }
+
+    /// Writes the last batches, and returns the numbers of label and evaluation batches.
+    pub fn finish(self) -> io::Result<(usize, usize)> {
+        let mut batches = (0, 0);
+        if let Some(labels) = self.labels {
+            batches.0 = labels.finish()?;
+            println!("Wrote {} batches of labels", batches.0);
+        }
+        if let Some(evals) = self.evals {
+            batches.1 = evals.finish()?;
+            println!("Wrote {} batches of evaluations", batches.1);
+        }
+        Ok(batches)
+    }
+}
@@ -0,0 +1,146 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use dataset_manifest::{write_atomically, write_postcard_atomically};
+use radix_trie::Trie;
+use serde::{Deserialize, Serialize};
+
+use crate::extract::GameCounts;
+use crate::hash_counts::HashCounts;
+use crate::position_stats::StatsCounts;
+use crate::sketch::HeavyHitters;
+use crate::spill::SpilledRuns;
+use crate::stream::ResumePoint;
+
+/// Everything counted so far in an extraction.
+#[derive(Default, Serialize, Deserialize)]
+pub struct CountState {
+    pub board_trie: Trie<Vec<u8>, usize>,
+    pub hash_counts: HashCounts,
+    /// The sketch of `--approximate`, made when the first boards are counted.
+    pub heavy_hitters: Option<HeavyHitters>,
+    pub game_counts: GameCounts,
+    /// The position stats, if they are aggregated.
+    pub position_stats: Option<StatsCounts>,
+    /// The runs spilled to disk before the checkpoint, which stay there until the end.
+    pub spilled: SpilledRuns,
+}
+
+/// Saved as `checkpoint.json` in the checkpoint directory.
+///
+/// The counts are saved in a separate file, which is only replaced
+/// after the checkpoint pointing to the new one has been written,
+/// so a crash while saving leaves the previous checkpoint usable.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// The source that was being read, so that a different one is not resumed by mistake.
+    pub source: String,
+    pub resume: ResumePoint,
+    pub game_counts: GameCounts,
+    /// The name of the file in the checkpoint directory with the [`CountState`].
+    pub counts_file: String,
+    /// The number of label and evaluation batches that were written,
+    /// so that the batches after them are numbered the same way again.
+    pub label_batches: usize,
+    pub eval_batches: usize,
+}
+
+/// Saves and loads the checkpoints of the extraction into one trie,
+/// in the directory `<trie file name>.checkpoint` next to it.
+pub struct Checkpointer {
+    dir: PathBuf,
+    source: String,
+    /// How many games to read between checkpoints, or `None` to never save them.
+    pub every_games: Option<u64>,
+    saved: u64,
+}
+
+impl Checkpointer {
+    pub fn new(out_path: &Path, source: String, every_games: Option<u64>) -> Checkpointer {
+        let mut dir = out_path.as_os_str().to_owned();
+        dir.push(".checkpoint");
+        Checkpointer {
+            dir: dir.into(),
+            source,
+            every_games,
+            saved: 0,
+        }
+    }
+
+    /// Loads the last checkpoint, if there is one.
+    pub fn load(&self) -> io::Result<Option<(Checkpoint, CountState)>> {
+        let Some(checkpoint) = self.read_checkpoint()? else {
+            return Ok(None);
+        };
+        if checkpoint.source != self.source {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "the checkpoint in {} is for {}, not {}",
+                    self.dir.display(),
+                    checkpoint.source,
+                    self.source
+                ),
+            ));
+        }
+        let counts = std::fs::read(self.dir.join(&checkpoint.counts_file))?;
+        let state = postcard::from_bytes(&counts)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(Some((checkpoint, state)))
+    }
+
+    /// Saves the counts, which go up to the resume point,
+    /// and the numbers of label and evaluation batches written until then.
+    pub fn save(
+        &mut self,
+        resume: ResumePoint,
+        (label_batches, eval_batches): (usize, usize),
+        state: &CountState,
+    ) -> io::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        let previous_counts = self.read_checkpoint()?.map(|c| c.counts_file);
+        let counts_file = format!("counts-{}-{}.postcard", std::process::id(), self.saved);
+        self.saved += 1;
+
+        write_postcard_atomically(&self.dir.join(&counts_file), state)?;
+
+        let checkpoint = Checkpoint {
+            source: self.source.clone(),
+            resume,
+            game_counts: state.game_counts,
+            counts_file,
+            label_batches,
+            eval_batches,
+        };
+        write_atomically(&self.dir.join("checkpoint.json"), |out| {
+            Ok(serde_json::to_writer_pretty(out, &checkpoint)?)
+        })?;
+
+        if let Some(previous_counts) = previous_counts {
+            std::fs::remove_file(self.dir.join(previous_counts))?;
+        }
+        println!(
+            "Saved checkpoint after {} games, resuming at byte {}",
+            checkpoint.game_counts.accepted + checkpoint.game_counts.skipped,
+            checkpoint.resume.offset
+        );
+        Ok(())
+    }
+
+    /// The current checkpoint, without its counts.
+    fn read_checkpoint(&self) -> io::Result<Option<Checkpoint>> {
+        match std::fs::File::open(self.dir.join("checkpoint.json")) {
+            Ok(file) => Ok(Some(serde_json::from_reader(io::BufReader::new(file))?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Deletes the checkpoints, once the trie has been written.
+    pub fn remove(&self) -> io::Result<()> {
+        match std::fs::remove_dir_all(&self.dir) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+}
@@ -0,0 +1,258 @@
+use std::path::PathBuf;
+
+use clap::{Parser, ValueEnum};
+use dataset_manifest::ArtifactKind;
+pub use dataset_manifest::Month;
+
+use crate::filter::GameFilter;
+use crate::sampling::PositionSampling;
+
+/// Downloads monthly lichess databases and counts how often every board appears in them.
+///
+/// For every month in the range, a board trie is written to the output directory,
+/// unless a trie covering that month is already there.
+/// Local PGN files can be read instead with `--input`.
+#[derive(Parser, Debug, Clone)]
+#[command(version)]
+pub struct Args {
+    /// The first month to extract, as YYYY-MM.
+    #[arg(long, default_value = "2013-01")]
+    pub from: Month,
+
+    /// The last month to extract, as YYYY-MM.
+    #[arg(long, default_value = "2018-12")]
+    pub to: Month,
+
+    /// The directory where the board tries are stored.
+    #[arg(long, default_value = "../hugedata")]
+    pub output_dir: PathBuf,
+
+    /// The URL of the compressed PGN database for a month.
+    /// `{year}` is replaced by the year and `{month}` by the two-digit month.
+    #[arg(
+        long,
+        default_value = "https://database.lichess.org/standard/lichess_db_standard_rated_{year}-{month}.pgn.zst"
+    )]
+    pub url_template: String,
+
+    /// Read games from a local PGN file, plain or zstd-compressed,
+    /// from every `.pgn` and `.pgn.zst` file in a directory,
+    /// or from standard input if this is `-`, instead of downloading months.
+    #[arg(long, conflicts_with_all = ["from", "to", "url_template"])]
+    pub input: Option<PathBuf>,
+
+    /// The name of the trie file written for `--input`.
+    /// By default, it is based on the name of the input.
+    #[arg(long, requires = "input")]
+    pub output_name: Option<String>,
+
+    /// Also write the move played and the game result for every position,
+    /// in batches under `labels/<trie name>/` in the output directory.
+    #[arg(long)]
+    pub labels: bool,
+
+    /// Also write the lichess `[%eval]` annotations of the games as evaluation batches,
+    /// like the ones made with Stockfish by `fish_teacher`,
+    /// under `evals/<trie name>/` in the output directory.
+    #[arg(long)]
+    pub evals: bool,
+
+    /// Also aggregate the results, average ratings and moves played in every position,
+    /// like an opening explorer, into `<trie name>-position-stats.postcard` next to the trie.
+    /// Positions reached fewer than `--min-count` times are left out,
+    /// and only the 16 most common moves of a position are kept, see `compact_board::stats`.
+    #[arg(long)]
+    pub stats: bool,
+
+    /// Save the counts so far every this many games,
+    /// in a `<trie name>.checkpoint` directory next to the trie.
+    /// Only downloads and single files can be resumed, so this is ignored for other inputs.
+    #[arg(long)]
+    pub checkpoint_every: Option<u64>,
+
+    /// Continue from the checkpoint of an earlier extraction into the same trie, if there is one.
+    /// Reading restarts at the zstd frame of the checkpoint, fetched with an HTTP range request.
+    /// The lichess databases are a single frame, so they are downloaded and decompressed
+    /// again from their start, and only the counting of the games before the checkpoint is skipped.
+    #[arg(long)]
+    pub resume: bool,
+
+    /// Only read the games and report what would be counted, as `<trie name>.dry-run.json`:
+    /// games per rating band, boards per piece count, and estimates of the repeat rate
+    /// and of how the number of distinct boards grows with the number of games.
+    /// No trie, labels or evaluations are written.
+    #[arg(long, conflicts_with_all = ["resume", "checkpoint_every"])]
+    pub dry_run: bool,
+
+    /// The format of the counted boards.
+    /// Counts that were spilled to disk, see `--max-boards-in-memory`, are always written as records,
+    /// since a trie would have to hold all of them in memory.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Trie)]
+    pub output_format: OutputFormat,
+
+    #[command(flatten)]
+    pub counting: CountingOptions,
+
+    #[command(flatten)]
+    pub filter: GameFilter,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// A postcard-serialized board trie, which the other tools read.
+    Trie,
+    /// A record file of `compact_board::records`, which can be written and merged
+    /// without holding all the boards in memory.
+    Records,
+}
+
+impl OutputFormat {
+    /// The end of the names of the files in this format.
+    pub fn file_suffix(self) -> &'static str {
+        match self {
+            OutputFormat::Trie => "board-trie.postcard",
+            OutputFormat::Records => "board-counts.records",
+        }
+    }
+
+    pub fn artifact_kind(self) -> ArtifactKind {
+        match self {
+            OutputFormat::Trie => ArtifactKind::BoardTrie,
+            OutputFormat::Records => ArtifactKind::BoardCounts,
+        }
+    }
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct CountingOptions {
+    /// Only keep boards that were seen at least this many times in the month.
+    /// 1 keeps every board.
+    #[arg(long, default_value_t = 2)]
+    pub min_count: usize,
+
+    /// The memory budget, as the number of boards the trie may hold
+    /// before the counts are spilled to disk next to the output, to be merged at the end.
+    /// When counting by hash, an intermediate compaction is performed instead.
+    /// The position stats of `--stats` are always spilled the same way, with the same budget.
+    /// The default was found by monitoring RAM.
+    #[arg(long, default_value_t = 32_768_000)]
+    pub max_boards_in_memory: usize,
+
+    /// Count boards in a table keyed by their hash instead of in the board trie.
+    /// This takes far less memory per board, about an eighth,
+    /// so eight times as many boards are held before compaction,
+    /// but only the boards that repeat are kept.
+    #[arg(long)]
+    pub count_by_hash: bool,
+
+    /// Count boards approximately in a count-min sketch, whose size only depends on the error bounds,
+    /// and only keep the boards whose estimated count reaches `--min-count`.
+    /// The estimates are never too low, and too high by at most `--sketch-epsilon`
+    /// times the number of boards read, except with probability `--sketch-delta`.
+    /// The default bounds take 4 rows of 272 million counters, about 4.3 GB,
+    /// all of which is also written to every checkpoint of `--checkpoint-every`.
+    #[arg(long, conflicts_with = "count_by_hash")]
+    pub approximate: bool,
+
+    /// The error bound of `--approximate`, as a fraction of the number of boards read.
+    /// The sketch grows with its inverse.
+    #[arg(long, default_value_t = 1e-8, value_parser = parse_fraction)]
+    pub sketch_epsilon: f64,
+
+    /// The probability that an estimate of `--approximate` is off by more than the error bound.
+    #[arg(long, default_value_t = 0.02, value_parser = parse_fraction)]
+    pub sketch_delta: f64,
+
+    /// The most boards kept by `--approximate`. When more reach `--min-count`,
+    /// only the most frequent ones are kept.
+    #[arg(long, default_value_t = 10_000_000, value_parser = clap::value_parser!(u64).range(1..))]
+    pub top_k: u64,
+
+    #[command(flatten)]
+    pub sampling: PositionSampling,
+}
+
+/// Parses a number strictly between 0 and 1.
+fn parse_fraction(value: &str) -> Result<f64, String> {
+    let fraction: f64 = value.parse().map_err(|e| format!("{e}"))?;
+    if fraction > 0.0 && fraction < 1.0 {
+        Ok(fraction)
+    } else {
+        Err(format!("{value} is not between 0 and 1"))
+    }
+}
+
+impl CountingOptions {
+    /// Whether boards are counted by their hash, exactly or not, instead of by their compact encoding.
+    pub fn counts_hashes(&self) -> bool {
+        self.count_by_hash || self.approximate
+    }
+
+    pub fn trimming(&self) -> bool {
+        self.min_count > 1
+    }
+
+    /// Whether a board with the given trie value should be dropped when trimming.
+    /// Like in the trie, a value of 0 means that the board was seen once.
+    pub fn should_trim(&self, value: usize) -> bool {
+        value + 1 < self.min_count
+    }
+}
+
+impl Args {
+    /// How the games and positions are selected, as recorded in the manifest.
+    /// Counting every position of every game exactly is recorded as null,
+    /// like the files from before the manifest, so that the two can be merged.
+    pub fn selection(&self) -> serde_json::Value {
+        let counting = &self.counting;
+        if self.filter.is_empty()
+            && counting.sampling == PositionSampling::default()
+            && !counting.approximate
+        {
+            return serde_json::Value::Null;
+        }
+        serde_json::json!({
+            "games": self.filter,
+            "positions": counting.sampling,
+            "approximate": counting.approximate,
+        })
+    }
+
+    pub fn url(&self, month: Month) -> String {
+        self.url_template
+            .replace("{year}", &month.year.to_string())
+            .replace("{month}", &format!("{:0>2}", month.month))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_default_selection_is_null() {
+        let args = Args::parse_from(["position_extractor"]);
+        assert_eq!(args.selection(), serde_json::Value::Null);
+        let args = Args::parse_from(["position_extractor", "--min-count", "5"]);
+        assert_eq!(args.selection(), serde_json::Value::Null);
+        let args = Args::parse_from(["position_extractor", "--min-elo", "2000"]);
+        assert_eq!(args.selection()["games"]["min_elo"], 2000);
+    }
+
+    #[test]
+    fn test_sketch_bounds_are_fractions() {
+        for bound in ["--sketch-epsilon", "--sketch-delta"] {
+            for value in ["0", "1", "-0.5", "inf", "NaN"] {
+                let args = ["position_extractor", "--approximate", bound, value];
+                assert!(Args::try_parse_from(args).is_err());
+            }
+        }
+        let args = [
+            "position_extractor",
+            "--approximate",
+            "--sketch-epsilon",
+            "1e-6",
+        ];
+        assert_eq!(Args::parse_from(args).counting.sketch_epsilon, 1e-6);
+    }
+}
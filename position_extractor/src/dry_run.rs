@@ -0,0 +1,174 @@
+use std::collections::BTreeMap;
+use std::io;
+
+use compact_board::PositionHash;
+use serde::Serialize;
+
+use crate::cli::CountingOptions;
+use crate::extract::{self, GameCounts, ParsedGames};
+use crate::filter::GameFilter;
+use crate::stream::GameSplitter;
+use crate::visitor::AllPositions;
+
+/// The width of the rating bands of the histogram.
+const RATING_BAND: u32 = 200;
+
+/// The number of bits of the hash that pick a register of the [`DistinctCounter`].
+const PRECISION: u32 = 14;
+
+/// Estimates the number of distinct hashes with a HyperLogLog of 2^14 registers,
+/// which has a standard error of about 0.8%.
+pub struct DistinctCounter {
+    registers: Vec<u8>,
+}
+
+impl Default for DistinctCounter {
+    fn default() -> Self {
+        DistinctCounter {
+            registers: vec![0; 1 << PRECISION],
+        }
+    }
+}
+
+impl DistinctCounter {
+    pub fn add(&mut self, hash: PositionHash) {
+        let hash = hash.as_u64();
+        let index = (hash >> (64 - PRECISION)) as usize;
+        // The position of the first set bit after the index bits, with a set bit after them as a stop.
+        let rank = ((hash << PRECISION) | (1 << (PRECISION - 1))).leading_zeros() as u8 + 1;
+        self.registers[index] = self.registers[index].max(rank);
+    }
+
+    pub fn estimate(&self) -> u64 {
+        let m = self.registers.len() as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self.registers.iter().map(|r| 2f64.powi(-(*r as i32))).sum();
+        let estimate = alpha * m * m / sum;
+        let empty = self.registers.iter().filter(|r| **r == 0).count();
+        if estimate <= 2.5 * m && empty > 0 {
+            // Linear counting is more accurate for small numbers of hashes.
+            (m * (m / empty as f64).ln()).round() as u64
+        } else {
+            estimate.round() as u64
+        }
+    }
+}
+
+/// A point of the curve of how the number of distinct boards grows with the number of games.
+#[derive(Serialize, Debug)]
+pub struct GrowthPoint {
+    pub games: u64,
+    pub positions: u64,
+    pub distinct_positions: u64,
+}
+
+/// What an extraction with the same options would count, without the counts themselves.
+#[derive(Serialize, Debug, Default)]
+pub struct DryRunReport {
+    pub games_accepted: u64,
+    pub games_skipped: u64,
+    /// The number of boards that would be counted, including repeats.
+    pub positions: u64,
+    /// The estimated number of different boards among them.
+    pub distinct_positions: u64,
+    /// The estimated fraction of the boards that had already been seen before.
+    pub repeat_rate: f64,
+    /// The number of accepted games by the average rating of the players,
+    /// keyed by the lower end of a 200-point band.
+    pub games_per_rating_band: BTreeMap<u32, u64>,
+    /// The number of accepted games without the ratings of both players.
+    pub unrated_games: u64,
+    /// The number of boards by the number of pieces on them, kings included.
+    pub positions_per_piece_count: BTreeMap<u32, u64>,
+    /// The number of distinct boards after every doubling of the number of games read.
+    pub distinct_growth: Vec<GrowthPoint>,
+}
+
+/// Reads and parses the games like [`extract::count_boards`] would, with the same filter
+/// and sampling, but only reports statistics about them, using little memory.
+pub fn survey(
+    games: GameSplitter,
+    filter: &GameFilter,
+    options: &CountingOptions,
+) -> io::Result<DryRunReport> {
+    let new_visitor = || AllPositions::new(filter.clone(), options.sampling.clone(), false, false);
+    let mut report = DryRunReport::default();
+    let mut game_counts = GameCounts::default();
+    let mut distinct = DistinctCounter::default();
+    let mut next_growth_point = 1;
+    extract::parse_batches(games, new_visitor, false, |parsed, _, _| {
+        for parsed in parsed.iter() {
+            game_counts.accepted += parsed.accepted;
+            game_counts.skipped += parsed.skipped;
+            add_games(&mut report, &mut distinct, parsed);
+        }
+        let games = game_counts.accepted + game_counts.skipped;
+        if games >= next_growth_point {
+            next_growth_point = games * 2;
+            report.distinct_growth.push(GrowthPoint {
+                games,
+                positions: report.positions,
+                distinct_positions: distinct.estimate(),
+            });
+            println!("{:?}", report.distinct_growth.last().unwrap());
+        }
+    })?;
+
+    report.games_accepted = game_counts.accepted;
+    report.games_skipped = game_counts.skipped;
+    report.unrated_games =
+        game_counts.accepted - report.games_per_rating_band.values().sum::<u64>();
+    report.distinct_positions = distinct.estimate().min(report.positions);
+    if report.positions > 0 {
+        report.repeat_rate = 1.0 - report.distinct_positions as f64 / report.positions as f64;
+    }
+    report.distinct_growth.push(GrowthPoint {
+        games: game_counts.accepted + game_counts.skipped,
+        positions: report.positions,
+        distinct_positions: report.distinct_positions,
+    });
+    Ok(report)
+}
+
+fn add_games(report: &mut DryRunReport, distinct: &mut DistinctCounter, parsed: &ParsedGames) {
+    for game in parsed.games.iter() {
+        // Rejected games have no rating, and are left out of the bands.
+        if let Some(elo) = game.average_elo {
+            let band = elo / RATING_BAND * RATING_BAND;
+            *report.games_per_rating_band.entry(band).or_insert(0) += 1;
+        }
+        for (board, hash) in game.boards.iter() {
+            report.positions += 1;
+            distinct.add(*hash);
+            let pieces = board.occupied().count() as u32;
+            *report.positions_per_piece_count.entry(pieces).or_insert(0) += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Random-looking hashes, like the real ones, from the splitmix64 generator.
+    fn hash(i: u64) -> PositionHash {
+        let mut x = i.wrapping_mul(0x9e3779b97f4a7c15);
+        x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+        PositionHash((x ^ (x >> 31)) as u128)
+    }
+
+    #[test]
+    fn test_distinct_estimate() {
+        let mut distinct = DistinctCounter::default();
+        // Adding the same hashes again does not change the estimate.
+        for round in 0..3 {
+            for i in 0..100_000 {
+                distinct.add(hash(i));
+            }
+            let estimate = distinct.estimate();
+            assert!((97_000..103_000).contains(&estimate), "{round}: {estimate}");
+        }
+        assert_eq!(DistinctCounter::default().estimate(), 0);
+    }
+}
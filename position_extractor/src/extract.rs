@@ -0,0 +1,330 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::mpsc::SyncSender;
+
+use radix_trie::TrieCommon;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::batches::GameOutputs;
+use crate::checkpoint::{Checkpointer, CountState};
+use crate::cli::CountingOptions;
+use crate::filter::GameFilter;
+use crate::output::BoardCounts;
+use crate::position_stats::StatsCounts;
+use crate::stream::{GameSplitter, GameText, ResumePoint};
+use crate::visitor::{AllPositions, GamePositions};
+
+/// How many games were read, and how many of them passed the filter.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct GameCounts {
+    pub accepted: u64,
+    pub skipped: u64,
+}
+
+/// How many games are read from the stream before they are parsed together on the thread pool.
+const GAMES_PER_BATCH: usize = 4096;
+
+/// Counts every board in the games that pass the filter, adding to the counts in `state`.
+///
+/// The games are parsed by [`parse_batches`], so decompression, parsing and counting
+/// all happen at the same time.
+/// The games of a batch are still written to `outputs` and counted in the order of the stream.
+///
+/// Like in the saved tries, a value of 0 means that the board was seen once.
+/// If the counts did not fit in memory, they are returned as runs on disk instead of a trie.
+/// The labels and evaluations of the positions of those games are written to `outputs`,
+/// if it has writers for them, and the position stats are returned if `state` has them.
+/// If the checkpointer is set to save checkpoints, the counts are saved every so often,
+/// together with the place in the stream to resume reading from.
+/// If reading the stream fails, the error is returned and the last checkpoint is left alone.
+pub fn count_boards(
+    games: GameSplitter,
+    filter: &GameFilter,
+    options: &CountingOptions,
+    outputs: &mut GameOutputs,
+    mut state: CountState,
+    checkpointer: &mut Checkpointer,
+) -> io::Result<(BoardCounts, Option<StatsCounts>, GameCounts)> {
+    // The stats are aggregated from the labels, even if they are not written.
+    let collect_labels = outputs.labels.is_some() || state.position_stats.is_some();
+    let collect_evals = outputs.evals.is_some();
+    let new_visitor = || {
+        AllPositions::new(
+            filter.clone(),
+            options.sampling.clone(),
+            collect_labels,
+            collect_evals,
+        )
+    };
+    let mut games_since_checkpoint = 0;
+    parse_batches(
+        games,
+        new_visitor,
+        !options.counts_hashes(),
+        |mut parsed, games_in_batch, resume| {
+            for parsed in parsed.iter_mut() {
+                state.game_counts.accepted += parsed.accepted;
+                state.game_counts.skipped += parsed.skipped;
+                for game in parsed.games.iter_mut() {
+                    if let Some(position_stats) = &mut state.position_stats {
+                        position_stats.add(&game.labels);
+                    }
+                    outputs.add(game).unwrap();
+                }
+            }
+            count_batch(&mut state, &parsed, options);
+            if let Some(position_stats) = &mut state.position_stats {
+                if position_stats.len() > options.max_boards_in_memory {
+                    position_stats.spill().unwrap();
+                }
+            }
+
+            games_since_checkpoint += games_in_batch as u64;
+            if checkpointer
+                .every_games
+                .is_some_and(|every| games_since_checkpoint >= every)
+            {
+                games_since_checkpoint = 0;
+                let batches = outputs.flush().unwrap();
+                checkpointer.save(resume, batches, &state).unwrap();
+            }
+        },
+    )?;
+    let CountState {
+        mut board_trie,
+        hash_counts,
+        heavy_hitters,
+        game_counts,
+        position_stats,
+        mut spilled,
+    } = state;
+    println!(
+        "Games counted: {}, skipped by the filter: {}",
+        game_counts.accepted, game_counts.skipped
+    );
+    if !spilled.is_empty() {
+        // The boards still in memory become the last run,
+        // and trimming happens while the runs are merged.
+        if !board_trie.is_empty() {
+            spilled.spill(&mut board_trie).unwrap();
+        }
+        return Ok((BoardCounts::Spilled(spilled), position_stats, game_counts));
+    }
+    if options.count_by_hash {
+        println!("Hashed board count: {}", hash_counts.len());
+        board_trie = hash_counts.into_trie();
+    }
+    if let Some(heavy_hitters) = heavy_hitters {
+        println!("Frequent board count: {}", heavy_hitters.len());
+        board_trie = heavy_hitters.into_trie();
+    }
+    let len = board_trie.len();
+    println!("Board count: {}", len);
+    println!("Counting boards to trim...");
+    let mut to_trim = 0;
+    for (_k, v) in board_trie.iter() {
+        if options.should_trim(*v) {
+            to_trim += 1;
+        }
+    }
+    println!(
+        "Boards seen fewer than {} times: {to_trim}",
+        options.min_count
+    );
+
+    if options.trimming() {
+        println!("PERFORMING ISOLATED TRIMMING");
+        let mut more_keys: bool = true;
+        while more_keys {
+            more_keys = false;
+            let mut keys_to_delete = vec![];
+            for (k, v) in board_trie.iter() {
+                if options.should_trim(*v) {
+                    keys_to_delete.push(k.clone());
+                }
+                if keys_to_delete.len() > 128 * 1024 {
+                    more_keys = true;
+                    break;
+                }
+            }
+            for key in keys_to_delete.drain(0..) {
+                board_trie.remove(&key);
+            }
+            println!(
+                "Still remaining to trim: {}",
+                to_trim - (len - board_trie.len())
+            );
+        }
+    }
+
+    Ok((
+        BoardCounts::InMemory(board_trie),
+        position_stats,
+        game_counts,
+    ))
+}
+
+/// Reads the games of the stream on their own thread,
+/// and parses them in batches on the rayon thread pool.
+///
+/// `on_batch` gets the parsed games of every batch in the order of the stream,
+/// with the number of games in the batch and the place to resume from to read the games after it.
+/// The compact boards of the games are only made if `compact_boards` is set.
+/// Reading stops at the first error of the stream, which is returned.
+pub fn parse_batches(
+    games: GameSplitter,
+    new_visitor: impl Fn() -> AllPositions + Sync + Send,
+    compact_boards: bool,
+    mut on_batch: impl FnMut(Vec<ParsedGames>, usize, ResumePoint),
+) -> io::Result<()> {
+    std::thread::scope(|scope| {
+        let (batch_tx, batch_rx) = std::sync::mpsc::sync_channel(2);
+        scope.spawn(move || read_batches(games, batch_tx));
+
+        for batch in batch_rx {
+            let (batch, resume) = batch?;
+            let parsed = batch
+                .par_iter()
+                .map_init(&new_visitor, |visitor, game| {
+                    parse_games(visitor, game, compact_boards)
+                })
+                .collect();
+            on_batch(parsed, batch.len(), resume);
+        }
+        Ok(())
+    })
+}
+
+/// A batch of games, and the place to resume from to read the games after it.
+type Batch = (Vec<GameText>, ResumePoint);
+
+/// Splits the stream into batches of games, and sends each batch
+/// together with the place to resume from to read the games after it.
+/// An error of the stream is sent instead of the batch it happened in.
+fn read_batches(mut games: GameSplitter, batch_tx: SyncSender<io::Result<Batch>>) {
+    loop {
+        let mut batch = Vec::with_capacity(GAMES_PER_BATCH);
+        while batch.len() < GAMES_PER_BATCH {
+            match games.next_game() {
+                Ok(Some(game)) => batch.push(game),
+                Ok(None) => break,
+                Err(e) => {
+                    let _ = batch_tx.send(Err(e));
+                    return;
+                }
+            }
+        }
+        let finished = batch.len() < GAMES_PER_BATCH;
+        if !batch.is_empty() && batch_tx.send(Ok((batch, games.resume_point()))).is_err() {
+            return;
+        }
+        if finished {
+            return;
+        }
+    }
+}
+
+/// The games in the text of one game from the splitter, which is usually just one.
+pub struct ParsedGames {
+    pub games: Vec<GamePositions>,
+    /// The compact boards of the games, unless boards are counted by hash.
+    pub compact_boards: Vec<Vec<u8>>,
+    pub accepted: u64,
+    pub skipped: u64,
+}
+
+fn parse_games(visitor: &mut AllPositions, game: &GameText, compact_boards: bool) -> ParsedGames {
+    let (accepted, skipped) = (visitor.games_accepted, visitor.games_skipped);
+    let mut games = vec![];
+    let mut reader = pgn_reader::BufferedReader::new_cursor(&game.text[..]);
+    while let Some(pos) = reader.read_game(visitor).unwrap() {
+        games.push(pos);
+    }
+
+    let mut compact = vec![];
+    if compact_boards {
+        for pos in games.iter() {
+            for (board, _hash) in pos.boards.iter() {
+                compact.push(compact_board::board_to_compact(board).into_bytes());
+            }
+        }
+    }
+
+    ParsedGames {
+        games,
+        compact_boards: compact,
+        accepted: visitor.games_accepted - accepted,
+        skipped: visitor.games_skipped - skipped,
+    }
+}
+
+/// Adds the boards of a batch of games to the counts,
+/// spilling them to disk, or compacting them when counting by hash,
+/// if they take more memory than allowed.
+/// In approximate mode, the memory is bounded by the sketch and the top-K boards instead.
+///
+/// The boards are first counted on each thread, and only the totals are added to the counts.
+fn count_batch(state: &mut CountState, parsed: &[ParsedGames], options: &CountingOptions) {
+    let board_trie = &mut state.board_trie;
+    let hash_counts = &mut state.hash_counts;
+    if options.counts_hashes() {
+        let batch_counts = parsed
+            .par_iter()
+            .flat_map_iter(|parsed| parsed.games.iter())
+            .flat_map_iter(|pos| pos.boards.iter())
+            .fold(HashMap::new, |mut counts, (board, hash)| {
+                counts.entry(*hash).or_insert((0, board)).0 += 1;
+                counts
+            })
+            .reduce(HashMap::new, |mut counts, other| {
+                for (hash, (times, board)) in other {
+                    counts.entry(hash).or_insert((0, board)).0 += times;
+                }
+                counts
+            });
+        if options.approximate {
+            let heavy_hitters = state
+                .heavy_hitters
+                .as_mut()
+                .expect("approximate counting starts with a sketch");
+            for (hash, (times, board)) in batch_counts {
+                heavy_hitters.add(hash, board, times);
+            }
+            println!("{}", heavy_hitters.len());
+            return;
+        }
+        for (hash, (times, board)) in batch_counts {
+            hash_counts.add(hash, board, times);
+        }
+        println!("{}", hash_counts.len());
+        if hash_counts.len() > options.max_boards_in_memory * 8 {
+            // The hash table takes about an eighth of the memory of the trie per board
+            println!("Performing intermediate compaction");
+            hash_counts.drop_uniques();
+        }
+        return;
+    }
+
+    let batch_counts = parsed
+        .par_iter()
+        .flat_map_iter(|parsed| parsed.compact_boards.iter())
+        .fold(HashMap::new, |mut counts, board| {
+            *counts.entry(board).or_insert(0) += 1;
+            counts
+        })
+        .reduce(HashMap::new, |mut counts, other| {
+            for (board, times) in other {
+                *counts.entry(board).or_insert(0) += times;
+            }
+            counts
+        });
+    for (board, times) in batch_counts {
+        // Increment the counter associated with this board state.
+        board_trie.map_with_default(board.clone(), |v| *v += times, times - 1);
+    }
+    println!("{}", board_trie.len());
+    if board_trie.len() > options.max_boards_in_memory {
+        state.spilled.spill(board_trie).unwrap();
+    }
+}
@@ -0,0 +1,222 @@
+use clap::ValueEnum;
+use serde::Serialize;
+
+/// The speed of a game, as lichess classifies it from the estimated game duration:
+/// the base time plus 40 times the increment.
+#[derive(ValueEnum, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TimeControlClass {
+    UltraBullet,
+    Bullet,
+    Blitz,
+    Rapid,
+    Classical,
+    Correspondence,
+}
+
+impl TimeControlClass {
+    /// Classifies a `TimeControl` header value, like `300+3`, or `-` for correspondence games.
+    pub fn from_header(value: &str) -> Option<TimeControlClass> {
+        if value == "-" {
+            return Some(TimeControlClass::Correspondence);
+        }
+        let (base, increment) = value.split_once('+')?;
+        let base: u32 = base.parse().ok()?;
+        let increment: u32 = increment.parse().ok()?;
+        let estimated = base + 40 * increment;
+        Some(if estimated < 30 {
+            TimeControlClass::UltraBullet
+        } else if estimated < 180 {
+            TimeControlClass::Bullet
+        } else if estimated < 480 {
+            TimeControlClass::Blitz
+        } else if estimated < 1500 {
+            TimeControlClass::Rapid
+        } else {
+            TimeControlClass::Classical
+        })
+    }
+}
+
+/// Which games to count, decided from their headers alone.
+///
+/// Every condition that is not set accepts all games.
+/// A game that lacks a header needed by a condition is rejected.
+#[derive(clap::Args, Serialize, Debug, Clone, Default)]
+pub struct GameFilter {
+    /// Only count games where both players are rated at least this much.
+    #[arg(long)]
+    pub min_elo: Option<u32>,
+
+    /// Only count games where both players are rated at most this much.
+    #[arg(long)]
+    pub max_elo: Option<u32>,
+
+    /// Only count games with these time controls, separated by commas.
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub time_controls: Vec<TimeControlClass>,
+
+    /// Only count games whose `Termination` header is one of these, separated by commas,
+    /// like `Normal` or `Time forfeit`. Case is ignored.
+    #[arg(long, value_delimiter = ',')]
+    pub terminations: Vec<String>,
+
+    /// Only count rated games.
+    #[arg(long)]
+    pub rated_only: bool,
+}
+
+impl GameFilter {
+    pub fn is_empty(&self) -> bool {
+        self.min_elo.is_none()
+            && self.max_elo.is_none()
+            && self.time_controls.is_empty()
+            && self.terminations.is_empty()
+            && !self.rated_only
+    }
+
+    pub fn accepts(&self, headers: &GameHeaders) -> bool {
+        if self.min_elo.is_some() || self.max_elo.is_some() {
+            for elo in [headers.white_elo, headers.black_elo] {
+                let Some(elo) = elo else {
+                    return false;
+                };
+                if self.min_elo.is_some_and(|min| elo < min)
+                    || self.max_elo.is_some_and(|max| elo > max)
+                {
+                    return false;
+                }
+            }
+        }
+
+        if !self.time_controls.is_empty() {
+            match headers.time_control {
+                Some(class) if self.time_controls.contains(&class) => {}
+                _ => return false,
+            }
+        }
+
+        if !self.terminations.is_empty() {
+            let Some(termination) = &headers.termination else {
+                return false;
+            };
+            if !self
+                .terminations
+                .iter()
+                .any(|t| t.eq_ignore_ascii_case(termination))
+            {
+                return false;
+            }
+        }
+
+        !self.rated_only || headers.rated
+    }
+}
+
+/// The headers of a game that the filter looks at.
+#[derive(Debug, Clone, Default)]
+pub struct GameHeaders {
+    pub white_elo: Option<u32>,
+    pub black_elo: Option<u32>,
+    pub time_control: Option<TimeControlClass>,
+    pub termination: Option<String>,
+    /// Lichess names the event of rated games like `Rated Blitz game`.
+    pub rated: bool,
+}
+
+impl GameHeaders {
+    /// Remembers the header if it is one that the filter uses.
+    pub fn record(&mut self, key: &[u8], value: &[u8]) {
+        let Ok(value) = std::str::from_utf8(value) else {
+            return;
+        };
+        match key {
+            b"WhiteElo" => self.white_elo = value.parse().ok(),
+            b"BlackElo" => self.black_elo = value.parse().ok(),
+            b"TimeControl" => self.time_control = TimeControlClass::from_header(value),
+            b"Termination" => self.termination = Some(value.to_string()),
+            b"Event" => self.rated = value.starts_with("Rated"),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn headers(pairs: &[(&str, &str)]) -> GameHeaders {
+        let mut headers = GameHeaders::default();
+        for (key, value) in pairs {
+            headers.record(key.as_bytes(), value.as_bytes());
+        }
+        headers
+    }
+
+    #[test]
+    fn test_time_control_classes() {
+        assert_eq!(
+            TimeControlClass::from_header("15+0"),
+            Some(TimeControlClass::UltraBullet)
+        );
+        assert_eq!(
+            TimeControlClass::from_header("60+1"),
+            Some(TimeControlClass::Bullet)
+        );
+        assert_eq!(
+            TimeControlClass::from_header("180+2"),
+            Some(TimeControlClass::Blitz)
+        );
+        assert_eq!(
+            TimeControlClass::from_header("600+5"),
+            Some(TimeControlClass::Rapid)
+        );
+        assert_eq!(
+            TimeControlClass::from_header("1800+30"),
+            Some(TimeControlClass::Classical)
+        );
+        assert_eq!(
+            TimeControlClass::from_header("-"),
+            Some(TimeControlClass::Correspondence)
+        );
+        assert_eq!(TimeControlClass::from_header("?"), None);
+    }
+
+    #[test]
+    fn test_filter() {
+        let game = headers(&[
+            ("Event", "Rated Blitz game"),
+            ("WhiteElo", "1850"),
+            ("BlackElo", "2010"),
+            ("TimeControl", "300+0"),
+            ("Termination", "Normal"),
+        ]);
+        assert!(GameFilter::default().accepts(&game));
+
+        let filter = GameFilter {
+            min_elo: Some(1800),
+            time_controls: vec![TimeControlClass::Blitz, TimeControlClass::Rapid],
+            terminations: vec!["normal".to_string()],
+            rated_only: true,
+            ..GameFilter::default()
+        };
+        assert!(filter.accepts(&game));
+
+        let weak = headers(&[("WhiteElo", "800"), ("BlackElo", "2010")]);
+        assert!(!filter.accepts(&weak));
+
+        let casual = headers(&[("Event", "Casual Blitz game")]);
+        assert!(!GameFilter {
+            rated_only: true,
+            ..GameFilter::default()
+        }
+        .accepts(&casual));
+
+        let no_elo = headers(&[("WhiteElo", "?"), ("BlackElo", "?")]);
+        assert!(!GameFilter {
+            max_elo: Some(3000),
+            ..GameFilter::default()
+        }
+        .accepts(&no_elo));
+    }
+}
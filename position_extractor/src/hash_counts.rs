@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+
+use compact_board::{CompactBoard, PositionHash};
+use serde::{Deserialize, Serialize};
+use shakmaty::Board;
+
+/// Counts boards by their 64-bit hash instead of their compact encoding.
+///
+/// A board seen only once costs just its hash and counter.
+/// The compact encoding is only kept for boards seen at least twice,
+/// so the boards seen once cannot be written out at the end:
+/// this mode always behaves as if isolated trimming was enabled.
+///
+/// Like in the board trie, a count of 0 means that the board was seen once.
+#[derive(Default, Serialize, Deserialize)]
+pub struct HashCounts {
+    counts: HashMap<u64, u32>,
+    boards: HashMap<u64, CompactBoard>,
+}
+
+impl HashCounts {
+    pub fn len(&self) -> usize {
+        self.counts.len()
+    }
+
+    /// Counts a board that was seen `times` times, which must be at least once.
+    pub fn add(&mut self, hash: PositionHash, board: &Board, times: u32) {
+        let hash = hash.as_u64();
+        let count = match self.counts.get_mut(&hash) {
+            Some(count) => {
+                *count = count.saturating_add(times);
+                *count
+            }
+            None => {
+                self.counts.insert(hash, times - 1);
+                times - 1
+            }
+        };
+        if count > 0 {
+            self.boards
+                .entry(hash)
+                .or_insert_with(|| compact_board::board_to_compact(board));
+        }
+    }
+
+    /// Forgets all the boards that have only been seen once so far.
+    pub fn drop_uniques(&mut self) {
+        self.counts.retain(|_, count| *count > 0);
+    }
+
+    /// Converts the repeated boards into the same trie that the default mode produces.
+    pub fn into_trie(self) -> radix_trie::Trie<Vec<u8>, usize> {
+        let mut board_trie = radix_trie::Trie::new();
+        for (hash, board) in self.boards {
+            board_trie.insert(board.into_bytes(), self.counts[&hash] as usize);
+        }
+        board_trie
+    }
+}
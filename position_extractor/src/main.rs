@@ -1,289 +1,338 @@
-use std::io::{self, Read};
-
+use std::collections::BTreeSet;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use clap::{Parser, ValueEnum};
+use dataset_manifest::{
+    write_atomically, write_postcard_checked, Artifact, ArtifactKind, Manifest,
+};
 use radix_trie::TrieCommon;
-use shakmaty::{Board, Chess, Position};
-
-use pgn_reader::{SanPlus, Skip, Visitor};
-use rayon::prelude::*;
-use tokio::io::AsyncReadExt;
-
-struct AllPositions {
-    positions: Vec<Board>,
-    current_pos: Chess,
-}
-
-impl AllPositions {
-    fn new() -> AllPositions {
-        AllPositions {
-            positions: vec![],
-            current_pos: Chess::new(),
-        }
-    }
-}
-
-impl Visitor for AllPositions {
-    type Result = Vec<Board>;
-
-    fn begin_variation(&mut self) -> Skip {
-        Skip(true) // stay in the mainline
-    }
-
-    fn san(&mut self, san_plus: SanPlus) {
-        if let Ok(m) = san_plus.san.to_move(&self.current_pos) {
-            self.current_pos.play_unchecked(&m);
-            self.positions.push(self.current_pos.board().clone());
-        }
-    }
-
-    fn end_game(&mut self) -> Self::Result {
-        self.current_pos = Chess::new();
-        ::std::mem::replace(&mut self.positions, vec![])
-    }
-}
-
-struct BytesStreamReader {
-    pub data_recv: tokio::sync::mpsc::Receiver<Vec<u8>>,
-}
-
-impl Read for BytesStreamReader {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        loop {
-            let data = self.data_recv.blocking_recv();
-            match data {
-                Some(d) => {
-                    for (src_byte, dst_byte) in d.iter().zip(buf.iter_mut()) {
-                        *dst_byte = *src_byte;
-                    }
-                    return Ok(d.len());
-                }
-                None => {
-                    println!("Decompression thread finished!");
-                    return Ok(0);
-                }
-            }
-        }
-    }
-}
+use serde::Serialize;
+
+mod batches;
+mod checkpoint;
+mod cli;
+mod dry_run;
+mod extract;
+mod filter;
+mod hash_counts;
+mod output;
+mod position_stats;
+mod sampling;
+mod sketch;
+mod source;
+mod spill;
+mod stream;
+mod visitor;
+
+use batches::{BatchWriter, GameOutputs};
+use checkpoint::{Checkpointer, CountState};
+use cli::{Args, Month, OutputFormat};
+use filter::GameFilter;
+use position_stats::StatsCounts;
+use sampling::PositionSampling;
+use sketch::HeavyHitters;
+use source::Source;
+use spill::SpilledRuns;
+use stream::GameSplitter;
 
 #[tokio::main]
 async fn main() -> io::Result<()> {
     tracing_subscriber::fmt::init();
+    let args = Args::parse();
+
+    if let Some(input) = &args.input {
+        let source = Source::local(input);
+        let output_name = args
+            .output_name
+            .clone()
+            .unwrap_or_else(|| source.default_output_name(args.output_format.file_suffix()));
+        let out_path = args.output_dir.join(output_name);
+        return extract(&source, out_path, BTreeSet::new(), &args).await;
+    }
 
-    for year in 2013..=2018 {
-        for month in 1..=12 {
-            download_data(year, month).await?;
-        }
+    for month in args.from.range_to(args.to) {
+        download_data(&args, month).await?;
     }
     Ok(())
 }
 
-const PERFORM_ISOLATED_TRIMMING: bool = true;
-
 #[global_allocator]
 static ALLOCATOR: jemallocator::Jemalloc = jemallocator::Jemalloc;
 
-async fn download_data(year: i32, month: i32) -> io::Result<()> {
-    let output_file = format!("single-{year}-{month}-board-trie.postcard");
-
-    // Check for tries that contain this month already
-    for file in std::fs::read_dir("../hugedata").unwrap() {
-        let file_name = file.unwrap().file_name();
-        let name = file_name.to_string_lossy();
-        println!("{name}");
-        if name == output_file {
-            println!("Not downloading for {year}-{month} because this file is already present");
-            return Ok(());
-        }
-        if let Some(prefix) = name.strip_suffix("-board-tries.postcard") {
-            if let Some(main) = prefix.strip_prefix("combined-") {
-                let parts: Vec<_> = main.split("+").collect();
-                let left_parts: Vec<_> = parts[0].split("-").collect();
-                let right_parts: Vec<_> = parts[1].split("-").collect();
-                let left_year: i32 = (left_parts[0]).parse().unwrap();
-                let left_month: i32 = (left_parts[1]).parse().unwrap();
-                let right_year: i32 = (right_parts[0]).parse().unwrap();
-                let right_month: i32 = (right_parts[1]).parse().unwrap();
-                let mut current_month = left_month;
-                let mut current_year = left_year;
-                while !(current_month == right_month && current_year == right_year) {
-                    if current_month == month && current_year == year {
-                        println!("Not downloading for {year}-{month} because there is a file that covers range {left_year}-{left_month} to {right_year}-{right_month}");
-                        return Ok(());
-                    }
-                    current_month += 1;
-                    if current_month > 12 {
-                        current_month = 1;
-                        current_year += 1;
-                    }
-                }
-
-                if current_month == month && current_year == year {
-                    println!("Not downloading for {year}-{month} because there is a file that covers range {left_year}-{left_month} to {right_year}-{right_month}");
-                    return Ok(());
-                }
-            }
-        }
+async fn download_data(args: &Args, month: Month) -> io::Result<()> {
+    let output_file = format!(
+        "single-{}-{}-{}",
+        month.year,
+        month.month,
+        args.output_format.file_suffix()
+    );
+
+    let manifest = Manifest::load(&args.output_dir)?;
+    // Months whose counts were spilled are written as records, even if a trie was asked for.
+    let kinds = [
+        args.output_format.artifact_kind(),
+        ArtifactKind::BoardCounts,
+    ];
+    if let Some(covering) = kinds
+        .iter()
+        .find_map(|kind| manifest.covering(*kind, month))
+    {
+        println!("Not downloading for {month} because {covering} already covers it");
+        return Ok(());
     }
 
-    use futures::stream::TryStreamExt;
-    use tokio_util::compat::FuturesAsyncReadCompatExt;
-    let response = reqwest::get(format!(
-        "https://database.lichess.org/standard/lichess_db_standard_rated_{year}-{month:0>2}.pgn.zst"
-    ))
-    .await
-    .unwrap();
-    let total_len = response.content_length().unwrap_or(1);
-    let mut data = response
-        // .bytes()
-        // .await
-        // .unwrap();
-        // let data = Box::new(data);
-        // let data = &Box::leak(data)[..];
-        .bytes_stream()
-        .map_ok(|v| v.to_vec())
-        .map_err(|e| futures::io::Error::new(futures::io::ErrorKind::Other, e))
-        .into_async_read()
-        .compat();
+    let source = Source::Remote {
+        url: args.url(month),
+    };
+    let out_path = args.output_dir.join(&output_file);
+    extract(&source, out_path, BTreeSet::from([month]), args).await
+}
 
-    let (tx, rx) = tokio::sync::mpsc::channel::<Vec<u8>>(512 * 1024);
+/// Written next to every board trie as `<trie file name>.meta.json`,
+/// so it is known which games the counts come from.
+#[derive(Serialize)]
+struct ExtractionMeta {
+    source: String,
+    filter: GameFilter,
+    sampling: PositionSampling,
+    min_count: usize,
+    games_accepted: u64,
+    games_skipped: u64,
+    boards: usize,
+    /// The number of positions in the position stats, if they were aggregated.
+    positions_with_stats: Option<usize>,
+}
 
-    tokio::spawn(async move {
-        let mut buf = [0u8; 16 * 1024];
-        let mut downloaded_so_far = 0;
-        let total_len_float = total_len as f64;
-        loop {
-            let len = data.read(&mut buf).await;
-            match len {
-                Ok(v) => {
-                    downloaded_so_far += v;
-                    if v > 0 {
-                        println!(
-                            "Read compressed data so far: {downloaded_so_far} \t/\t{total_len}\t{}",
-                            ((downloaded_so_far as f64) / total_len_float) * 100.0
-                        );
-                    }
-                    let data = Vec::from_iter(buf[..v].iter().map(|v| *v));
-                    if let Err(_) = tx.send(data).await {
-                        break;
-                    }
-                }
-                Err(e) => {
-                    println!("Error while reading compressed data: {e}");
-                    drop(tx);
-                    break;
-                }
-            }
+/// Counts the boards in the games of the source and saves the board trie,
+/// recording everything written in the manifest of the output directory
+/// as coming from the given months.
+async fn extract(
+    source: &Source,
+    out_path: PathBuf,
+    months: BTreeSet<Month>,
+    args: &Args,
+) -> io::Result<()> {
+    if args.dry_run {
+        return dry_run(source, out_path, args).await;
+    }
+    println!("Extracting {source:?} into {}", out_path.display());
+    if !args.filter.is_empty() {
+        println!("Only counting games that pass {:?}", args.filter);
+    }
+    let source_description = format!("{source:?}");
+    let every_games = args.checkpoint_every.filter(|_| source.resumable());
+    let mut checkpointer = Checkpointer::new(&out_path, source_description.clone(), every_games);
+    let resumed = if args.resume {
+        checkpointer.load()?
+    } else {
+        None
+    };
+    let (resume, mut state, label_batches, eval_batches) = match resumed {
+        Some((checkpoint, state)) => {
+            println!("Resuming from {checkpoint:?}");
+            let resume = Some(checkpoint.resume);
+            (
+                resume,
+                state,
+                checkpoint.label_batches,
+                checkpoint.eval_batches,
+            )
         }
-    });
-
-    let compressed_data_blocking = BytesStreamReader { data_recv: rx };
-
-    let mut decompressed_stream = zstd::Decoder::new(compressed_data_blocking).unwrap();
-
-    tokio::task::spawn_blocking(move || {
-        let mut board_trie: radix_trie::Trie<Vec<u8>, usize> = radix_trie::Trie::new();
-        let mut reader = pgn_reader::BufferedReader::new(&mut decompressed_stream);
-
-        let mut visitor = AllPositions::new();
-        loop {
-            let pos = reader.read_game(&mut visitor).unwrap();
-            let pos = match pos {
-                None => break,
-                Some(pos) => pos,
+        None => {
+            let mut spill_dir = out_path.as_os_str().to_owned();
+            spill_dir.push(".spill");
+            let mut stats_spill_dir = out_path.as_os_str().to_owned();
+            stats_spill_dir.push(".stats-spill");
+            let state = CountState {
+                spilled: SpilledRuns::new(spill_dir.into()),
+                position_stats: args.stats.then(|| StatsCounts::new(stats_spill_dir.into())),
+                ..Default::default()
             };
-
-            // println!("{} positions", pos.len());
-
-            let compact_boards: Vec<Vec<u8>> = pos
-                .par_iter()
-                .map(|board| compact_board::board_to_compact(&board))
-                .collect();
-            for board in compact_boards {
-                // let reverse = compact_board::compact_slice_to_board(&compact).unwrap();
-                // assert_eq!(board, reverse);
-
-                // Increment the counter associated with this board state.
-                board_trie.map_with_default(board, |v| *v += 1, 0);
-                if board_trie.len() % 10000 == 0 {
-                    println!("{}", board_trie.len());
-                }
-            }
-
-            if PERFORM_ISOLATED_TRIMMING && board_trie.len() > 32_768_000 {
-                // value found by monitoring RAM
-                println!("Performing intermediate compaction");
-                let mut repeats = 0;
-                for (_k, v) in board_trie.iter() {
-                    if *v != 0 {
-                        repeats += 1;
-                    }
-                }
-
-                let mut new_board_trie: radix_trie::Trie<Vec<u8>, usize> = radix_trie::Trie::new();
-                for (k, v) in board_trie.iter() {
-                    if *v > 0 {
-                        new_board_trie.insert(k.clone(), *v);
-                        if new_board_trie.len() % 1000 == 0 {
-                            println!("Copied values: {} out of {}", new_board_trie.len(), repeats);
-                        }
-                    }
-                }
-                println!("All values copied over, replacing old trie with new one");
-                let old_board_trie = std::mem::replace(&mut board_trie, new_board_trie);
-                println!("Dropping old trie...");
-                drop(old_board_trie);
-            }
-        }
-        let len = board_trie.len();
-        println!("Board count: {}", len);
-        println!("Counting boards with only 1 state...");
-        let mut uniques = 0;
-        for (_k, v) in board_trie.iter() {
-            if *v == 0 {
-                uniques += 1;
-            }
+            (None, state, 0, 0)
         }
-        println!("Unique boards: {uniques}");
+    };
+    // The sketch is made before reading, so that error bounds that are too tight fail right away.
+    if args.counting.approximate && state.heavy_hitters.is_none() {
+        state.heavy_hitters = Some(HeavyHitters::new(&args.counting)?);
+    }
 
-        if PERFORM_ISOLATED_TRIMMING {
-            println!("PERFORMING ISOLATED TRIMMING");
-            let mut more_keys: bool = true;
-            while more_keys {
-                more_keys = false;
-                let mut keys_to_delete = vec![];
-                for (k, v) in board_trie.iter() {
-                    if *v == 0 {
-                        keys_to_delete.push(k.clone());
-                    }
-                    if keys_to_delete.len() > 128 * 1024 {
-                        more_keys = true;
-                        break;
-                    }
-                }
-                for key in keys_to_delete.drain(0..) {
-                    board_trie.remove(&key);
-                }
-                println!(
-                    "Still remaining uniques: {}",
-                    uniques - (len - board_trie.len())
-                );
-            }
+    let mut outputs = GameOutputs::default();
+    if args.labels {
+        let dir = side_output_dir(&out_path, "labels");
+        let writer = BatchWriter::new(dir, "labels")?.starting_at(label_batches);
+        outputs.labels = Some(writer);
+    }
+    if args.evals {
+        let dir = side_output_dir(&out_path, "evals");
+        let writer = BatchWriter::new(dir, "batch")?.starting_at(eval_batches);
+        outputs.evals = Some(writer);
+    }
+    let stream = source.open(resume.map_or(0, |r| r.offset)).await?;
+    let filter = args.filter.clone();
+    let options = args.counting.clone();
+    let output_format = args.output_format;
+    let data_dir = args.output_dir.clone();
+    let label_dir = args.labels.then(|| side_output_dir(&out_path, "labels"));
+    let eval_dir = args.evals.then(|| side_output_dir(&out_path, "evals"));
+    let selection = args.selection();
+    tokio::task::spawn_blocking(move || {
+        let games = GameSplitter::new(stream, resume)?;
+        // If the stream fails, the checkpoint is kept to resume from.
+        let (board_counts, position_stats, game_counts) = extract::count_boards(
+            games,
+            &filter,
+            &options,
+            &mut outputs,
+            state,
+            &mut checkpointer,
+        )?;
+        let (label_batches, eval_batches) = outputs.finish()?;
+
+        println!("Board counts ready, saving...");
+        let written_format = board_counts.written_format(output_format);
+        let out_path = if written_format == output_format {
+            out_path
+        } else {
+            let path = out_path.with_file_name(format!(
+                "{}-{}",
+                output_stem(&out_path),
+                written_format.file_suffix()
+            ));
+            println!(
+                "The counts did not fit in memory, so they are written as records to {}",
+                path.display()
+            );
+            path
+        };
+        let output_format = written_format;
+        let boards = output::write_counts(board_counts, output_format, &options, &out_path)?;
+        let mut written = vec![(out_path.clone(), output_format.artifact_kind(), boards)];
+        let mut positions_with_stats = None;
+        if let Some(position_stats) = position_stats {
+            let stats_trie = position_stats.into_trie(options.min_count)?;
+            let stats_path = out_path.with_file_name(format!(
+                "{}-{}",
+                output_stem(&out_path),
+                output::POSITION_STATS_SUFFIX
+            ));
+            written.push((
+                stats_path.clone(),
+                ArtifactKind::PositionStats,
+                stats_trie.len(),
+            ));
+            println!(
+                "Saving the stats of {} positions to {}",
+                stats_trie.len(),
+                stats_path.display()
+            );
+            write_postcard_checked(&stats_path, &stats_trie)?;
+            positions_with_stats = Some(stats_trie.len());
         }
 
-        println!("Board trie ready, saving...");
-        let out_file = std::fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .open(format!("../hugedata/{output_file}"))
-            .unwrap();
-
-        let out_file_buf = std::io::BufWriter::new(out_file);
-
-        postcard::to_io(&board_trie, out_file_buf).unwrap();
+        if let Some(label_dir) = label_dir {
+            written.push((label_dir, ArtifactKind::Labels, label_batches));
+        }
+        if let Some(eval_dir) = eval_dir {
+            written.push((eval_dir, ArtifactKind::Evals, eval_batches));
+        }
+        record_artifacts(&data_dir, &written, &months, selection, options.min_count)?;
+
+        let meta = ExtractionMeta {
+            source: source_description,
+            filter,
+            sampling: options.sampling.clone(),
+            min_count: options.min_count,
+            games_accepted: game_counts.accepted,
+            games_skipped: game_counts.skipped,
+            boards,
+            positions_with_stats,
+        };
+        let mut meta_path = out_path.into_os_string();
+        meta_path.push(".meta.json");
+        write_atomically(Path::new(&meta_path), |out| {
+            Ok(serde_json::to_writer_pretty(out, &meta)?)
+        })?;
+        checkpointer.remove()
     })
     .await
-    .unwrap();
+    .unwrap()
+}
 
-    panic!();
+/// Reads the games of the source and saves the statistics of the dry run
+/// to `<trie file name>.dry-run.json`, instead of counting the boards.
+async fn dry_run(source: &Source, out_path: PathBuf, args: &Args) -> io::Result<()> {
+    println!("Surveying {source:?} without counting the boards");
+    let stream = source.open(0).await?;
+    let filter = args.filter.clone();
+    let options = args.counting.clone();
+    let report = tokio::task::spawn_blocking(move || {
+        let games = GameSplitter::new(stream, None)?;
+        dry_run::survey(games, &filter, &options)
+    })
+    .await
+    .unwrap()?;
+
+    let mut report_path = out_path.into_os_string();
+    report_path.push(".dry-run.json");
+    write_atomically(Path::new(&report_path), |out| {
+        Ok(serde_json::to_writer_pretty(out, &report)?)
+    })?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    println!(
+        "Saved the report to {}",
+        PathBuf::from(report_path).display()
+    );
     Ok(())
 }
+
+/// The directory for the batches of one kind extracted together with the trie at `out_path`:
+/// `<kind>/<trie name stem>` next to the trie.
+fn side_output_dir(out_path: &Path, kind: &str) -> PathBuf {
+    out_path.with_file_name(kind).join(output_stem(out_path))
+}
+
+/// The name of the trie at `out_path` without `-board-trie.postcard`,
+/// or without `-board-counts.records` for record files.
+fn output_stem(out_path: &Path) -> String {
+    let name = out_path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    OutputFormat::value_variants()
+        .iter()
+        .find_map(|format| name.strip_suffix(&format!("-{}", format.file_suffix())))
+        .unwrap_or(&name)
+        .to_string()
+}
+
+/// Records the files and batch directories written by an extraction, with their number of entries,
+/// in the manifest of the data directory.
+fn record_artifacts(
+    data_dir: &Path,
+    written: &[(PathBuf, ArtifactKind, usize)],
+    months: &BTreeSet<Month>,
+    selection: serde_json::Value,
+    min_count: usize,
+) -> io::Result<()> {
+    let mut artifacts = vec![];
+    for (path, kind, entries) in written {
+        let name = path.strip_prefix(data_dir).unwrap_or(path);
+        let crc32 = if path.is_file() {
+            Some(dataset_manifest::file_crc32(path)?)
+        } else {
+            None
+        };
+        let artifact = Artifact {
+            kind: *kind,
+            months: months.clone(),
+            filter: selection.clone(),
+            encoding_version: compact_board::FormatVersion::V1.tag(),
+            min_count: min_count.max(1) as u64,
+            entries: Some(*entries as u64),
+            crc32,
+        };
+        artifacts.push((name.to_string_lossy().into_owned(), artifact));
+    }
+    Manifest::update(data_dir, |manifest| manifest.artifacts.extend(artifacts))
+}
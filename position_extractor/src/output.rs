@@ -0,0 +1,95 @@
+use std::io;
+use std::path::Path;
+
+use compact_board::{write_trie_as_records, RecordWriter};
+use dataset_manifest::{write_checked, write_postcard_checked, AtomicWriter};
+use radix_trie::{Trie, TrieCommon};
+
+use crate::cli::{CountingOptions, OutputFormat};
+use crate::spill::SpilledRuns;
+
+/// The end of the names of the position stats tries, after the name of their board trie.
+pub const POSITION_STATS_SUFFIX: &str = "position-stats.postcard";
+
+/// The counted boards, either all in memory or spilled to disk.
+pub enum BoardCounts {
+    /// The boards seen at least the minimum number of times.
+    /// Like in the saved tries, a value of 0 means that the board was seen once.
+    InMemory(Trie<Vec<u8>, usize>),
+    /// All the boards, which have not been trimmed yet.
+    Spilled(SpilledRuns),
+}
+
+impl BoardCounts {
+    /// The format the counts are written in when `format` was asked for.
+    ///
+    /// Spilled counts are always written as records, since a trie would have to hold
+    /// every board in memory, and as much again to check the file it was written to.
+    pub fn written_format(&self, format: OutputFormat) -> OutputFormat {
+        match self {
+            BoardCounts::InMemory(_) => format,
+            BoardCounts::Spilled(_) => OutputFormat::Records,
+        }
+    }
+}
+
+/// Writes the boards seen at least the minimum number of times to `out_path`,
+/// and returns how many were written.
+///
+/// Spilled counts are merged while they are written, so they never have to fit in memory,
+/// which is why they can only be written as records: see [`BoardCounts::written_format`].
+/// The file only gets its name once it is complete and reads back
+/// with as many boards as were written, see [`write_checked`].
+pub fn write_counts(
+    counts: BoardCounts,
+    format: OutputFormat,
+    options: &CountingOptions,
+    out_path: &Path,
+) -> io::Result<usize> {
+    let len = match (&counts, format) {
+        (BoardCounts::InMemory(board_trie), OutputFormat::Trie) => {
+            write_postcard_checked(out_path, board_trie)?;
+            board_trie.len()
+        }
+        (BoardCounts::Spilled(_), OutputFormat::Trie) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "spilled counts can only be written as records",
+            ));
+        }
+        (_, OutputFormat::Records) => {
+            let check = |path: &Path, len: &u64| compact_board::check_record_count(path, *len);
+            let (len, _) =
+                write_checked(out_path, |out| write_records(&counts, options, out), check)?;
+            len as usize
+        }
+    };
+    // The runs are only deleted once their counts are safely written.
+    if let BoardCounts::Spilled(spilled) = counts {
+        spilled.remove()?;
+    }
+    Ok(len)
+}
+
+fn write_records(
+    counts: &BoardCounts,
+    options: &CountingOptions,
+    out: &mut AtomicWriter,
+) -> io::Result<u64> {
+    match counts {
+        BoardCounts::InMemory(board_trie) => write_trie_as_records(board_trie, out),
+        BoardCounts::Spilled(spilled) => {
+            println!("Merging the spilled runs into the record file...");
+            let mut writer = RecordWriter::new(out)?;
+            for record in spilled.merged()? {
+                let (board, count) = record?;
+                if !options.should_trim(count as usize - 1) {
+                    writer.push(&board, count)?;
+                }
+            }
+            let len = writer.len();
+            writer.finish()?;
+            Ok(len)
+        }
+    }
+}
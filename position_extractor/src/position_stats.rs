@@ -0,0 +1,202 @@
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use compact_board::{LabelRecord, PositionStats};
+use radix_trie::{Trie, TrieCommon};
+use serde::{Deserialize, Serialize};
+
+/// The [`PositionStats`] of every position that a move was played in,
+/// keyed by the position as written in the labels, by `position_to_compact` without clocks.
+///
+/// Unlike the board trie, the key includes the side to move and the castling rights,
+/// and the final positions of the games are not included, since no move was played in them.
+///
+/// Like the board counts, the stats that do not fit in memory are spilled to disk
+/// as runs sorted by position, which are merged at the end,
+/// so the stats are exact no matter how many runs there were,
+/// except for the rarest moves, see [`PositionStats::moves`].
+#[derive(Default, Serialize, Deserialize)]
+pub struct StatsCounts {
+    positions: Trie<Vec<u8>, PositionStats>,
+    spill_dir: PathBuf,
+    runs: Vec<PathBuf>,
+}
+
+impl StatsCounts {
+    pub fn new(spill_dir: PathBuf) -> StatsCounts {
+        StatsCounts {
+            spill_dir,
+            ..Default::default()
+        }
+    }
+
+    /// The number of positions in memory.
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    /// Adds the positions of a game, given as its labels.
+    pub fn add(&mut self, labels: &[LabelRecord]) {
+        for label in labels {
+            let stats = PositionStats::from_label(label);
+            self.positions.map_with_default(
+                label.position.clone(),
+                |v| v.merge(&stats),
+                stats.clone(),
+            );
+        }
+    }
+
+    /// Writes the stats in memory as a new run: the number of positions,
+    /// then every position and its stats in the byte order of the positions, all with postcard.
+    pub fn spill(&mut self) -> io::Result<()> {
+        std::fs::create_dir_all(&self.spill_dir)?;
+        let path = self
+            .spill_dir
+            .join(format!("stats-run-{}.postcard", self.runs.len()));
+        println!(
+            "Spilling the stats of {} positions to {}",
+            self.positions.len(),
+            path.display()
+        );
+        let mut file = BufWriter::new(std::fs::File::create(&path)?);
+        postcard::to_io(&(self.positions.len() as u64), &mut file).map_err(io::Error::other)?;
+        for entry in self.positions.iter() {
+            postcard::to_io(&entry, &mut file).map_err(io::Error::other)?;
+        }
+        file.flush()?;
+        self.runs.push(path);
+        self.positions = Trie::new();
+        Ok(())
+    }
+
+    /// The stats of the positions reached at least `min_count` times,
+    /// with the spilled runs merged in, which are then deleted.
+    pub fn into_trie(mut self, min_count: usize) -> io::Result<Trie<Vec<u8>, PositionStats>> {
+        let reached_enough = |stats: &PositionStats| stats.games as usize >= min_count;
+        if self.runs.is_empty() {
+            let rare: Vec<Vec<u8>> = self
+                .positions
+                .iter()
+                .filter(|(_, stats)| !reached_enough(stats))
+                .map(|(position, _)| position.clone())
+                .collect();
+            for position in rare {
+                self.positions.remove(&position);
+            }
+            return Ok(self.positions);
+        }
+
+        // The positions still in memory are merged like the others.
+        self.spill()?;
+        println!("Merging {} runs of position stats...", self.runs.len());
+        let mut positions = Trie::new();
+        merge_runs(&self.runs, |position, stats| {
+            if reached_enough(&stats) {
+                positions.insert(position, stats);
+            }
+        })?;
+        std::fs::remove_dir_all(&self.spill_dir)?;
+        Ok(positions)
+    }
+}
+
+/// Reads back a run written by [`StatsCounts::spill`], in the order of the positions.
+struct StatsRun {
+    reader: BufReader<std::fs::File>,
+    remaining: u64,
+}
+
+impl StatsRun {
+    fn open(path: &Path) -> io::Result<StatsRun> {
+        let mut run = StatsRun {
+            reader: BufReader::new(std::fs::File::open(path)?),
+            remaining: 1,
+        };
+        run.remaining = run.read()?;
+        Ok(run)
+    }
+
+    fn read<T: serde::de::DeserializeOwned>(&mut self) -> io::Result<T> {
+        let mut buf = [0; 4096];
+        let (value, _) =
+            postcard::from_io((&mut self.reader, &mut buf)).map_err(io::Error::other)?;
+        Ok(value)
+    }
+
+    fn next(&mut self) -> io::Result<Option<(Vec<u8>, PositionStats)>> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        self.read().map(Some)
+    }
+}
+
+/// Calls `f` with every position in the runs and the sum of its stats in all of them,
+/// in the order of the positions, holding only one position of every run in memory.
+fn merge_runs(runs: &[PathBuf], mut f: impl FnMut(Vec<u8>, PositionStats)) -> io::Result<()> {
+    let mut runs = runs
+        .iter()
+        .map(|path| StatsRun::open(path))
+        .collect::<io::Result<Vec<_>>>()?;
+    let mut heads = runs
+        .iter_mut()
+        .map(StatsRun::next)
+        .collect::<io::Result<Vec<_>>>()?;
+    loop {
+        let Some(position) = heads.iter().flatten().map(|(p, _)| p).min().cloned() else {
+            return Ok(());
+        };
+        let mut total: Option<PositionStats> = None;
+        for (head, run) in heads.iter_mut().zip(runs.iter_mut()) {
+            if head.as_ref().is_some_and(|(p, _)| *p == position) {
+                let (_, stats) = std::mem::replace(head, run.next()?).unwrap();
+                match &mut total {
+                    Some(total) => total.merge(&stats),
+                    None => total = Some(stats),
+                }
+            }
+        }
+        f(position, total.unwrap());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use compact_board::labels::GameResult;
+
+    fn label(position: u8, uci: &str) -> LabelRecord {
+        LabelRecord {
+            position: vec![position],
+            uci: uci.into(),
+            result: GameResult::WhiteWins,
+            white_elo: None,
+            black_elo: None,
+            ply: 0,
+        }
+    }
+
+    #[test]
+    fn test_spilled_stats_are_exact() {
+        let dir = std::env::temp_dir().join(format!("stats-spill-test-{}", std::process::id()));
+        let mut counts = StatsCounts::new(dir.clone());
+        counts.add(&[label(1, "e2e4"), label(2, "d2d4")]);
+        counts.spill().unwrap();
+        counts.add(&[label(1, "d2d4"), label(3, "c2c4")]);
+        counts.spill().unwrap();
+        counts.add(&[label(1, "e2e4"), label(3, "c2c4")]);
+
+        let trie = counts.into_trie(2).unwrap();
+        assert_eq!(trie.len(), 2);
+        let first = trie.get(&vec![1]).unwrap();
+        assert_eq!(first.games, 3);
+        assert_eq!(
+            first.moves,
+            [("e2e4".to_string(), 2), ("d2d4".to_string(), 1)]
+        );
+        assert_eq!(trie.get(&vec![3]).unwrap().games, 2);
+        assert!(!dir.exists());
+    }
+}
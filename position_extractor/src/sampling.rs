@@ -0,0 +1,143 @@
+use clap::ValueEnum;
+use compact_board::PositionHash;
+use serde::Serialize;
+use shakmaty::Board;
+
+/// The phase of the game, judged from the material on the board alone.
+#[derive(ValueEnum, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum GamePhase {
+    Opening,
+    Middlegame,
+    Endgame,
+}
+
+impl GamePhase {
+    /// Counts the pieces other than kings and pawns, weighted 1 for minor pieces,
+    /// 2 for rooks and 4 for queens, which is 24 in the starting position.
+    /// More than 20 is the opening, and 8 or less is the endgame.
+    pub fn of_board(board: &Board) -> GamePhase {
+        let weight = board.knights().count()
+            + board.bishops().count()
+            + 2 * board.rooks().count()
+            + 4 * board.queens().count();
+        if weight > 20 {
+            GamePhase::Opening
+        } else if weight > 8 {
+            GamePhase::Middlegame
+        } else {
+            GamePhase::Endgame
+        }
+    }
+}
+
+/// Which positions of the accepted games to keep.
+///
+/// Positions are counted by their ply, the number of half-moves played before them.
+/// The random sampling by phase is decided from the hash of the board and the seed,
+/// so a board is either always kept or always dropped, and its count stays exact.
+/// By default, every position is kept.
+#[derive(clap::Args, Serialize, Debug, Clone, PartialEq)]
+pub struct PositionSampling {
+    /// Skip the positions in the first this many plies of every game.
+    #[arg(long, default_value_t = 0)]
+    pub skip_plies: u16,
+
+    /// Only keep every this many-th ply after the skipped ones.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    pub every_nth_ply: u16,
+
+    /// The fraction of positions to keep in the opening, by material.
+    #[arg(long, default_value_t = 1.0)]
+    pub opening_fraction: f64,
+
+    /// The fraction of positions to keep in the middlegame, by material.
+    #[arg(long, default_value_t = 1.0)]
+    pub middlegame_fraction: f64,
+
+    /// The fraction of positions to keep in the endgame, by material.
+    #[arg(long, default_value_t = 1.0)]
+    pub endgame_fraction: f64,
+
+    /// Changes which boards are picked by the fractions.
+    #[arg(long, default_value_t = 0)]
+    pub sample_seed: u64,
+}
+
+impl Default for PositionSampling {
+    fn default() -> Self {
+        PositionSampling {
+            skip_plies: 0,
+            every_nth_ply: 1,
+            opening_fraction: 1.0,
+            middlegame_fraction: 1.0,
+            endgame_fraction: 1.0,
+            sample_seed: 0,
+        }
+    }
+}
+
+impl PositionSampling {
+    /// Whether the position with this board, whose board hash is `hash`, is kept at this ply.
+    pub fn keeps(&self, board: &Board, hash: PositionHash, ply: u16) -> bool {
+        if ply < self.skip_plies || !(ply - self.skip_plies).is_multiple_of(self.every_nth_ply) {
+            return false;
+        }
+        let fraction = match GamePhase::of_board(board) {
+            GamePhase::Opening => self.opening_fraction,
+            GamePhase::Middlegame => self.middlegame_fraction,
+            GamePhase::Endgame => self.endgame_fraction,
+        };
+        if fraction >= 1.0 {
+            return true;
+        }
+        // The upper half of the hash, mixed with the seed by the splitmix64 finalizer.
+        let mut x = ((hash.as_u128() >> 64) as u64) ^ self.sample_seed;
+        x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+        x ^= x >> 31;
+        (x as f64 / u64::MAX as f64) < fraction
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_phases() {
+        assert_eq!(GamePhase::of_board(&Board::new()), GamePhase::Opening);
+        let board = Board::from_ascii_board_fen(b"r3k3/pp3ppp/8/8/8/8/PP3PPP/2R1K1N1").unwrap();
+        assert_eq!(GamePhase::of_board(&board), GamePhase::Endgame);
+        let board =
+            Board::from_ascii_board_fen(b"r2qk2r/pp3ppp/2n5/8/8/5N2/PP3PPP/R2QK2R").unwrap();
+        assert_eq!(GamePhase::of_board(&board), GamePhase::Middlegame);
+    }
+
+    #[test]
+    fn test_ply_window() {
+        let sampling = PositionSampling {
+            skip_plies: 4,
+            every_nth_ply: 3,
+            ..Default::default()
+        };
+        let (board, hash) = (Board::new(), PositionHash::of_board(&Board::new()));
+        let kept: Vec<u16> = (0..12)
+            .filter(|ply| sampling.keeps(&board, hash, *ply))
+            .collect();
+        assert_eq!(kept, [4, 7, 10]);
+    }
+
+    #[test]
+    fn test_fraction() {
+        let sampling = PositionSampling {
+            opening_fraction: 0.25,
+            ..Default::default()
+        };
+        let board = Board::new();
+        let kept = (0..10_000u128)
+            .filter(|i| sampling.keeps(&board, PositionHash(i << 64), 1))
+            .count();
+        assert!((2000..3000).contains(&kept), "{kept}");
+    }
+}
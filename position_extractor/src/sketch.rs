@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+use std::io;
+
+use compact_board::{CompactBoard, PositionHash};
+use serde::{Deserialize, Serialize};
+use shakmaty::Board;
+
+use crate::cli::CountingOptions;
+
+/// A count-min sketch of how often every board hash was seen.
+///
+/// The estimates are never lower than the real counts, and are higher by at most
+/// `epsilon` times the number of boards counted, except with probability `delta`.
+/// The memory used only depends on these error bounds, not on the number of different boards.
+#[derive(Serialize, Deserialize)]
+pub struct CountMinSketch {
+    width: usize,
+    depth: usize,
+    counters: Vec<u32>,
+}
+
+impl CountMinSketch {
+    /// A sketch for the error bounds, which must both be between 0 and 1,
+    /// or an error if its counters would not even fit in the address space.
+    pub fn with_error_bounds(epsilon: f64, delta: f64) -> io::Result<CountMinSketch> {
+        let width = (std::f64::consts::E / epsilon).ceil() as usize;
+        let depth = (1.0 / delta).ln().ceil().max(1.0) as usize;
+        let len = width
+            .checked_mul(depth)
+            .filter(|len| len.checked_mul(std::mem::size_of::<u32>()).is_some())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "a sketch with the error bounds {epsilon:e} and {delta:e} is too large"
+                    ),
+                )
+            })?;
+        Ok(CountMinSketch {
+            width,
+            depth,
+            counters: vec![0; len],
+        })
+    }
+
+    pub fn memory_bytes(&self) -> usize {
+        self.counters.len() * std::mem::size_of::<u32>()
+    }
+
+    /// The counter of the hash in every row, picked with the two halves of the 128-bit hash.
+    fn cells(&self, hash: PositionHash) -> impl Iterator<Item = usize> {
+        let (width, hash) = (self.width as u64, hash.as_u128());
+        let (low, high) = (hash as u64, (hash >> 64) as u64 | 1);
+        (0..self.depth).map(move |row| {
+            row * width as usize
+                + (low.wrapping_add((row as u64).wrapping_mul(high)) % width) as usize
+        })
+    }
+
+    pub fn estimate(&self, hash: PositionHash) -> u32 {
+        self.cells(hash)
+            .map(|cell| self.counters[cell])
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// Counts a hash seen `times` times, and returns its new estimate.
+    ///
+    /// This is the conservative update: a counter is only raised up to the new estimate,
+    /// which keeps the counters shared with other hashes lower.
+    pub fn add(&mut self, hash: PositionHash, times: u32) -> u32 {
+        let estimate = self.estimate(hash).saturating_add(times);
+        for cell in self.cells(hash).collect::<Vec<_>>() {
+            self.counters[cell] = self.counters[cell].max(estimate);
+        }
+        estimate
+    }
+}
+
+/// Finds the boards that are seen often, without keeping the boards that are not.
+///
+/// Every board is counted in a [`CountMinSketch`], and its compact encoding is only kept
+/// once its estimated count reaches the threshold, which starts at `--min-count`.
+/// At most `--top-k` boards are kept: when there are twice as many,
+/// the threshold is raised to the count of the `top_k`-th board and the boards below it are dropped.
+///
+/// The counts written out are the estimates, so they can be slightly too high,
+/// and a board that only just reached the threshold may not really have.
+#[derive(Serialize, Deserialize)]
+pub struct HeavyHitters {
+    sketch: CountMinSketch,
+    threshold: u32,
+    top_k: usize,
+    boards: HashMap<u128, CompactBoard>,
+}
+
+impl HeavyHitters {
+    pub fn new(options: &CountingOptions) -> io::Result<HeavyHitters> {
+        let sketch =
+            CountMinSketch::with_error_bounds(options.sketch_epsilon, options.sketch_delta)?;
+        println!(
+            "Count-min sketch of {} x {} counters, using {} MB",
+            sketch.depth,
+            sketch.width,
+            sketch.memory_bytes() / (1024 * 1024)
+        );
+        Ok(HeavyHitters {
+            sketch,
+            threshold: options.min_count.max(1) as u32,
+            top_k: options.top_k as usize,
+            boards: HashMap::new(),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.boards.len()
+    }
+
+    /// Counts a board that was seen `times` times, which must be at least once.
+    pub fn add(&mut self, hash: PositionHash, board: &Board, times: u32) {
+        let estimate = self.sketch.add(hash, times);
+        if estimate < self.threshold || self.boards.contains_key(&hash.as_u128()) {
+            return;
+        }
+        self.boards
+            .insert(hash.as_u128(), compact_board::board_to_compact(board));
+        if self.boards.len() > self.top_k * 2 {
+            self.keep_top_k();
+        }
+    }
+
+    /// Raises the threshold to the count of the `top_k`-th most frequent board,
+    /// and drops the boards below it.
+    fn keep_top_k(&mut self) {
+        let mut estimates: Vec<u32> = self
+            .boards
+            .keys()
+            .map(|hash| self.sketch.estimate(PositionHash(*hash)))
+            .collect();
+        let index = estimates.len() - self.top_k;
+        let (_, kth, _) = estimates.select_nth_unstable(index);
+        self.threshold = self.threshold.max(*kth);
+        let (sketch, threshold) = (&self.sketch, self.threshold);
+        self.boards
+            .retain(|hash, _| sketch.estimate(PositionHash(*hash)) >= threshold);
+        println!(
+            "Keeping the {} boards seen at least {} times",
+            self.boards.len(),
+            self.threshold
+        );
+    }
+
+    /// Converts the frequent boards into the same trie that the default mode produces.
+    pub fn into_trie(self) -> radix_trie::Trie<Vec<u8>, usize> {
+        let mut board_trie = radix_trie::Trie::new();
+        for (hash, board) in self.boards {
+            let estimate = self.sketch.estimate(PositionHash(hash));
+            if estimate >= self.threshold {
+                board_trie.insert(board.into_bytes(), estimate as usize - 1);
+            }
+        }
+        board_trie
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Spreads small numbers over the whole hash, like real board hashes.
+    fn hash(i: u128) -> PositionHash {
+        PositionHash(i.wrapping_mul(0x9e3779b97f4a7c15f39cc0605cedc835))
+    }
+
+    #[test]
+    fn test_estimate_bounds() {
+        let times = |i: u128| if i < 10 { 50 } else { 1 };
+        let mut sketch = CountMinSketch::with_error_bounds(0.01, 0.01).unwrap();
+        let mut total = 0;
+        for i in 0..1000 {
+            sketch.add(hash(i), times(i));
+            total += times(i);
+        }
+        for i in 0..1000 {
+            let estimate = sketch.estimate(hash(i));
+            assert!(estimate >= times(i));
+            assert!(estimate as f64 <= times(i) as f64 + 0.01 * total as f64);
+        }
+    }
+
+    #[test]
+    fn test_too_large_error_bounds() {
+        assert!(CountMinSketch::with_error_bounds(1e-300, 0.5).is_err());
+    }
+}
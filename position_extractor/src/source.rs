@@ -0,0 +1,282 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use tokio::io::AsyncReadExt;
+
+use crate::stream::PgnStream;
+
+/// Where the PGN games are read from.
+#[derive(Debug, Clone)]
+pub enum Source {
+    /// A zstd-compressed PGN file downloaded over HTTP, like the lichess monthly databases.
+    Remote { url: String },
+    /// A local PGN file, plain or zstd-compressed.
+    File(PathBuf),
+    /// Every `.pgn` and `.pgn.zst` file in the directory, in the order of their names.
+    Directory(PathBuf),
+    /// Plain or zstd-compressed PGN on standard input.
+    Stdin,
+}
+
+impl Source {
+    /// The source for a local path, where `-` means standard input.
+    pub fn local(path: &Path) -> Source {
+        if path == Path::new("-") {
+            Source::Stdin
+        } else if path.is_dir() {
+            Source::Directory(path.to_path_buf())
+        } else {
+            Source::File(path.to_path_buf())
+        }
+    }
+
+    /// The name of the file to write when no name is given, ending with `suffix`.
+    pub fn default_output_name(&self, suffix: &str) -> String {
+        let stem = match self {
+            Source::Remote { url } => url.rsplit('/').next().unwrap_or(url).to_string(),
+            Source::File(path) | Source::Directory(path) => {
+                let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("input");
+                format!("local-{name}")
+            }
+            Source::Stdin => "local-stdin".to_string(),
+        };
+        let stem = stem.trim_end_matches(".zst").trim_end_matches(".pgn");
+        format!("{stem}-{suffix}")
+    }
+
+    /// Whether reading can start in the middle, so extraction from this source can be resumed.
+    pub fn resumable(&self) -> bool {
+        matches!(self, Source::Remote { .. } | Source::File(_))
+    }
+
+    /// Opens the source as a stream of uncompressed PGN,
+    /// starting at `offset` in the compressed data, which must be 0 unless the source is resumable.
+    ///
+    /// Remote data is downloaded by a background task, so this has to be called inside the runtime.
+    pub async fn open(&self, offset: u64) -> io::Result<PgnStream> {
+        if offset > 0 && !self.resumable() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("cannot start reading {self:?} in the middle"),
+            ));
+        }
+        match self {
+            Source::Remote { url } => open_remote(url, offset).await,
+            Source::File(path) => {
+                let mut file = File::open(path)?;
+                file.seek(SeekFrom::Start(offset))?;
+                PgnStream::new(file, offset)
+            }
+            Source::Directory(path) => {
+                let mut files = vec![];
+                for entry in std::fs::read_dir(path)? {
+                    let path = entry?.path();
+                    let name = path.to_string_lossy();
+                    if path.is_file() && (name.ends_with(".pgn") || name.ends_with(".pgn.zst")) {
+                        files.push(path);
+                    }
+                }
+                files.sort();
+                println!("Found {} PGN files in {}", files.len(), path.display());
+                let files = MultiFileReader {
+                    files: files.into_iter(),
+                    current: None,
+                };
+                PgnStream::new(files, 0)
+            }
+            Source::Stdin => PgnStream::new(io::stdin(), 0),
+        }
+    }
+}
+
+/// Reads the files one after another, opening each one only when the previous one is finished.
+struct MultiFileReader {
+    files: std::vec::IntoIter<PathBuf>,
+    current: Option<PgnStream>,
+}
+
+impl Read for MultiFileReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if let Some(current) = &mut self.current {
+                let len = current.read(buf)?;
+                if len > 0 {
+                    return Ok(len);
+                }
+                self.current = None;
+                // The last game of a file might not end with an empty line,
+                // so separate it from the first game of the next file.
+                let separator = b"\n\n";
+                let len = separator.len().min(buf.len());
+                buf[..len].copy_from_slice(&separator[..len]);
+                return Ok(len);
+            }
+            match self.files.next() {
+                Some(path) => {
+                    println!("Reading {}", path.display());
+                    self.current = Some(PgnStream::new(File::open(path)?, 0)?);
+                }
+                None => return Ok(0),
+            }
+        }
+    }
+}
+
+/// Reads the chunks downloaded by the task of [`open_remote`], waiting for each one,
+/// so it must only be read outside the runtime.
+///
+/// A download that fails is read as that error, not as the end of the data,
+/// so that the part of the month that was read is never taken for all of it.
+struct BytesStreamReader {
+    data_recv: tokio::sync::mpsc::Receiver<io::Result<Vec<u8>>>,
+    /// The last chunk received, and how much of it has been read.
+    /// A chunk can be larger than the buffer of a read, so the rest is kept for the next one.
+    chunk: Vec<u8>,
+    chunk_read: usize,
+}
+
+impl Read for BytesStreamReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        while self.chunk_read == self.chunk.len() {
+            match self.data_recv.blocking_recv() {
+                Some(chunk) => {
+                    self.chunk = chunk?;
+                    self.chunk_read = 0;
+                }
+                None => {
+                    println!("Decompression thread finished!");
+                    return Ok(0);
+                }
+            }
+        }
+        let rest = &self.chunk[self.chunk_read..];
+        let len = rest.len().min(buf.len());
+        buf[..len].copy_from_slice(&rest[..len]);
+        self.chunk_read += len;
+        Ok(len)
+    }
+}
+
+async fn open_remote(url: &str, offset: u64) -> io::Result<PgnStream> {
+    use futures::stream::TryStreamExt;
+    use tokio_util::compat::FuturesAsyncReadCompatExt;
+    let mut request = reqwest::Client::new().get(url);
+    if offset > 0 {
+        request = request.header(reqwest::header::RANGE, format!("bytes={offset}-"));
+    }
+    let response = request
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(io::Error::other)?;
+    if offset > 0 && response.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{url} does not support resuming from byte {offset}"),
+        ));
+    }
+    let total_len = offset + response.content_length().unwrap_or(1);
+    let mut data = response
+        .bytes_stream()
+        .map_ok(|v| v.to_vec())
+        .map_err(futures::io::Error::other)
+        .into_async_read()
+        .compat();
+
+    let (tx, rx) = tokio::sync::mpsc::channel::<io::Result<Vec<u8>>>(512 * 1024);
+
+    tokio::spawn(async move {
+        let mut buf = [0u8; 16 * 1024];
+        let mut downloaded_so_far = offset as usize;
+        let total_len_float = total_len as f64;
+        loop {
+            let len = data.read(&mut buf).await;
+            match len {
+                // The end of the download, which closes the channel.
+                Ok(0) => break,
+                Ok(v) => {
+                    downloaded_so_far += v;
+                    println!(
+                        "Read compressed data so far: {downloaded_so_far} \t/\t{total_len}\t{}",
+                        ((downloaded_so_far as f64) / total_len_float) * 100.0
+                    );
+                    if tx.send(Ok(buf[..v].to_vec())).await.is_err() {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    println!("Error while reading compressed data: {e}");
+                    let _ = tx.send(Err(e)).await;
+                    break;
+                }
+            }
+        }
+    });
+
+    let compressed_data_blocking = BytesStreamReader {
+        data_recv: rx,
+        chunk: vec![],
+        chunk_read: 0,
+    };
+
+    // Creating the stream already reads the first chunk to check for compression,
+    // which would block the runtime.
+    let stream =
+        tokio::task::spawn_blocking(move || PgnStream::new(compressed_data_blocking, offset))
+            .await
+            .unwrap()?;
+    // Anything else, like an error page, would be read as a month without games.
+    if !stream.is_compressed() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{url} is not zstd-compressed at byte {offset}"),
+        ));
+    }
+    Ok(stream)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_chunks_larger_than_buffer() {
+        let (tx, rx) = tokio::sync::mpsc::channel(4);
+        let data: Vec<u8> = (0..40_000).map(|i| (i % 251) as u8).collect();
+        for chunk in data.chunks(16 * 1024) {
+            tx.blocking_send(Ok(chunk.to_vec())).unwrap();
+        }
+        drop(tx);
+        let reader = BytesStreamReader {
+            data_recv: rx,
+            chunk: vec![],
+            chunk_read: 0,
+        };
+        let mut read = vec![];
+        io::BufReader::with_capacity(8 * 1024, reader)
+            .read_to_end(&mut read)
+            .unwrap();
+        assert_eq!(read, data);
+    }
+
+    #[test]
+    fn test_download_error_is_not_the_end() {
+        let (tx, rx) = tokio::sync::mpsc::channel(4);
+        tx.blocking_send(Ok(b"[Event ".to_vec())).unwrap();
+        tx.blocking_send(Err(io::Error::other("connection reset")))
+            .unwrap();
+        drop(tx);
+        let mut reader = BytesStreamReader {
+            data_recv: rx,
+            chunk: vec![],
+            chunk_read: 0,
+        };
+        let mut read = vec![];
+        assert!(reader.read_to_end(&mut read).is_err());
+        assert_eq!(read, b"[Event ");
+    }
+}
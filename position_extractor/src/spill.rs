@@ -0,0 +1,60 @@
+use std::io;
+use std::path::PathBuf;
+
+use compact_board::{write_trie_as_records, MergedRecords, RecordReader};
+use radix_trie::{Trie, TrieCommon};
+use serde::{Deserialize, Serialize};
+
+type Records = RecordReader<io::BufReader<std::fs::File>>;
+
+/// Board counts that did not fit in memory, written to disk as sorted runs of records.
+///
+/// Every time the trie reaches the memory budget, it is written out as a record file
+/// and counting continues with an empty trie.
+/// At the end, the runs are merged, which gives the exact count of every board
+/// no matter how many runs there were.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SpilledRuns {
+    dir: PathBuf,
+    runs: Vec<PathBuf>,
+}
+
+impl SpilledRuns {
+    pub fn new(dir: PathBuf) -> SpilledRuns {
+        SpilledRuns { dir, runs: vec![] }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.runs.is_empty()
+    }
+
+    /// Writes the counts in the trie as a new run, and empties the trie.
+    pub fn spill(&mut self, board_trie: &mut Trie<Vec<u8>, usize>) -> io::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        let path = self.dir.join(format!("run-{}.records", self.runs.len()));
+        println!("Spilling {} boards to {}", board_trie.len(), path.display());
+        let file = io::BufWriter::new(std::fs::File::create(&path)?);
+        write_trie_as_records(board_trie, file)?;
+        self.runs.push(path);
+        *board_trie = Trie::new();
+        Ok(())
+    }
+
+    /// The counts of all the runs, added up, in the order of the keys.
+    pub fn merged(&self) -> io::Result<MergedRecords<Records>> {
+        let mut inputs = vec![];
+        for run in self.runs.iter() {
+            let file = io::BufReader::new(std::fs::File::open(run)?);
+            inputs.push(RecordReader::new(file)?);
+        }
+        MergedRecords::new(inputs)
+    }
+
+    /// Deletes the runs, once their counts have been written out.
+    pub fn remove(self) -> io::Result<()> {
+        match std::fs::remove_dir_all(&self.dir) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+}
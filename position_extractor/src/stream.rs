@@ -0,0 +1,316 @@
+//! Reading PGN games while keeping track of where they are in the compressed stream,
+//! so that an extraction can be resumed from a checkpoint.
+//!
+//! A zstd stream can only be decoded from the start of a frame,
+//! so the place to resume from is given by the compressed offset of a frame start,
+//! and the number of games that had already been read from that frame.
+//! A plain PGN stream can be resumed right at the start of the next game.
+//!
+//! Files compressed as a single zstd frame, which includes the lichess databases,
+//! can only be resumed from their start. The counting work is still skipped,
+//! but the games before the checkpoint have to be read again.
+
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Read};
+
+use serde::{Deserialize, Serialize};
+
+/// The first bytes of every zstd frame.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Every game is split off at a line starting with this, which is the first header of lichess games.
+const GAME_START: &[u8] = b"[Event ";
+
+/// Where to continue reading a stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResumePoint {
+    /// The offset in the compressed stream to start reading from.
+    pub offset: u64,
+    /// How many games after that offset were already read.
+    pub skip_games: u64,
+}
+
+/// Counts the bytes read through it, starting from the offset that reading started at.
+struct CountingReader {
+    inner: Box<dyn Read + Send>,
+    count: u64,
+}
+
+impl Read for CountingReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.count += len as u64;
+        Ok(len)
+    }
+}
+
+/// The compressed offset of a zstd frame, and the decompressed offset where its data starts.
+#[derive(Debug, Clone, Copy)]
+struct FrameStart {
+    compressed: u64,
+    decompressed: u64,
+}
+
+enum Decoding {
+    Plain(BufReader<CountingReader>),
+    Zstd(Option<zstd::Decoder<'static, BufReader<CountingReader>>>),
+}
+
+/// A stream of uncompressed PGN, decompressed one zstd frame at a time if it is compressed.
+pub struct PgnStream {
+    decoding: Decoding,
+    /// The offset in the compressed stream where reading started.
+    base_offset: u64,
+    /// The number of decompressed bytes returned so far.
+    decompressed: u64,
+    /// The frames that started at or after the oldest game that is still being tracked.
+    frames: VecDeque<FrameStart>,
+}
+
+impl PgnStream {
+    /// Starts reading `reader`, which begins at `offset` in the compressed stream.
+    ///
+    /// The data is decompressed if it starts with a zstd frame.
+    pub fn new(reader: impl Read + Send + 'static, offset: u64) -> io::Result<PgnStream> {
+        let mut reader = BufReader::new(CountingReader {
+            inner: Box::new(reader),
+            count: offset,
+        });
+        let decoding = if reader.fill_buf()?.starts_with(&ZSTD_MAGIC) {
+            Decoding::Zstd(Some(zstd::Decoder::with_buffer(reader)?.single_frame()))
+        } else {
+            Decoding::Plain(reader)
+        };
+        Ok(PgnStream {
+            decoding,
+            base_offset: offset,
+            decompressed: 0,
+            frames: VecDeque::from([FrameStart {
+                compressed: offset,
+                decompressed: 0,
+            }]),
+        })
+    }
+
+    /// Whether the data is zstd-compressed.
+    pub fn is_compressed(&self) -> bool {
+        matches!(self.decoding, Decoding::Zstd(_))
+    }
+
+    /// The compressed offset to resume from to read the game starting at `game_start`,
+    /// a decompressed offset: the start of its frame, or the game itself in plain PGN.
+    ///
+    /// The game starts must be asked for in increasing order,
+    /// because the frames before them are forgotten.
+    fn frame_of(&mut self, game_start: u64) -> u64 {
+        if matches!(self.decoding, Decoding::Plain(_)) {
+            return self.base_offset + game_start;
+        }
+        while self.frames.len() > 1 && self.frames[1].decompressed <= game_start {
+            self.frames.pop_front();
+        }
+        self.frames[0].compressed
+    }
+}
+
+/// The offset in the compressed stream of the next byte that the decoder will read.
+fn compressed_offset(reader: &BufReader<CountingReader>) -> u64 {
+    reader.get_ref().count - reader.buffer().len() as u64
+}
+
+impl Read for PgnStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = match &mut self.decoding {
+            Decoding::Plain(reader) => reader.read(buf)?,
+            Decoding::Zstd(decoder) => loop {
+                let Some(current) = decoder else {
+                    break 0;
+                };
+                let len = current.read(buf)?;
+                if len > 0 || buf.is_empty() {
+                    break len;
+                }
+                // The frame is finished, so start decoding the next one, if there is one.
+                let mut reader = decoder.take().unwrap().finish();
+                if reader.fill_buf()?.is_empty() {
+                    break 0;
+                }
+                self.frames.push_back(FrameStart {
+                    compressed: compressed_offset(&reader),
+                    decompressed: self.decompressed,
+                });
+                *decoder = Some(zstd::Decoder::with_buffer(reader)?.single_frame());
+            },
+        };
+        self.decompressed += len as u64;
+        Ok(len)
+    }
+}
+
+/// The text of one game, and the decompressed offset where it starts.
+pub struct GameText {
+    pub text: Vec<u8>,
+    pub start: u64,
+}
+
+/// Splits a PGN stream into the texts of its games, at every line starting with `[Event `.
+///
+/// The text before the first such line is a game of its own,
+/// unless the stream was resumed in the middle, where it is the end of an earlier game and is dropped.
+pub struct GameSplitter {
+    reader: BufReader<PgnStream>,
+    /// The decompressed offset of the next line.
+    offset: u64,
+    /// The first line of the next game, if it has already been read.
+    next_start: Option<GameText>,
+    /// The frame of the last game returned, and how many games were returned from it.
+    frame: u64,
+    games_in_frame: u64,
+    started: bool,
+    skip_partial_game: bool,
+}
+
+impl GameSplitter {
+    /// Splits the games of the stream, skipping the games before the resume point.
+    pub fn new(stream: PgnStream, resume: Option<ResumePoint>) -> io::Result<GameSplitter> {
+        let frame = stream.base_offset;
+        let mut splitter = GameSplitter {
+            reader: BufReader::with_capacity(1 << 20, stream),
+            offset: 0,
+            next_start: None,
+            frame,
+            games_in_frame: 0,
+            started: false,
+            skip_partial_game: resume.is_some_and(|resume| resume.offset > 0),
+        };
+        if let Some(resume) = resume {
+            for _ in 0..resume.skip_games {
+                if splitter.next_game()?.is_none() {
+                    break;
+                }
+            }
+        }
+        Ok(splitter)
+    }
+
+    /// Reads one line into `line`, returning its offset, or `None` at the end of the stream.
+    fn read_line(&mut self, line: &mut Vec<u8>) -> io::Result<Option<u64>> {
+        line.clear();
+        let offset = self.offset;
+        let len = self.reader.read_until(b'\n', line)?;
+        self.offset += len as u64;
+        Ok((len > 0).then_some(offset))
+    }
+
+    pub fn next_game(&mut self) -> io::Result<Option<GameText>> {
+        let mut game = match self.next_start.take() {
+            Some(game) => game,
+            None if self.started => return Ok(None),
+            None => {
+                self.started = true;
+                let mut line = vec![];
+                loop {
+                    let Some(start) = self.read_line(&mut line)? else {
+                        return Ok(None);
+                    };
+                    if !self.skip_partial_game || line.starts_with(GAME_START) {
+                        break GameText { text: line, start };
+                    }
+                }
+            }
+        };
+
+        let mut line = vec![];
+        while let Some(start) = self.read_line(&mut line)? {
+            if line.starts_with(GAME_START) {
+                self.next_start = Some(GameText {
+                    text: std::mem::take(&mut line),
+                    start,
+                });
+                break;
+            }
+            game.text.extend_from_slice(&line);
+        }
+
+        let frame = self.reader.get_mut().frame_of(game.start);
+        if frame != self.frame {
+            self.frame = frame;
+            self.games_in_frame = 0;
+        }
+        self.games_in_frame += 1;
+        Ok(Some(game))
+    }
+
+    /// Where to resume to read the games after the last one that was returned.
+    pub fn resume_point(&self) -> ResumePoint {
+        ResumePoint {
+            offset: self.frame,
+            skip_games: self.games_in_frame,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const GAMES: &str = "[Event \"Rated Blitz game\"]\n[Result \"1-0\"]\n\n1. e4 e5 1-0\n\n\
+        [Event \"Rated Bullet game\"]\n[Result \"0-1\"]\n\n1. d4 d5 0-1\n\n\
+        [Event \"Casual game\"]\n[Result \"*\"]\n\n1. c4 *\n\n";
+
+    fn read_all(mut splitter: GameSplitter) -> Vec<(String, ResumePoint)> {
+        let mut games = vec![];
+        while let Some(game) = splitter.next_game().unwrap() {
+            let text = String::from_utf8(game.text).unwrap();
+            games.push((text, splitter.resume_point()));
+        }
+        games
+    }
+
+    fn compressed_frames() -> Vec<u8> {
+        // The second frame starts in the middle of the second game.
+        let (first, second) = GAMES.split_at(GAMES.find("1. d4").unwrap());
+        let mut data = zstd::encode_all(first.as_bytes(), 3).unwrap();
+        data.extend(zstd::encode_all(second.as_bytes(), 3).unwrap());
+        data
+    }
+
+    #[test]
+    fn test_resume_plain() {
+        let stream = PgnStream::new(GAMES.as_bytes(), 0).unwrap();
+        let games = read_all(GameSplitter::new(stream, None).unwrap());
+        assert_eq!(games.len(), 3);
+        assert!(games[1].0.starts_with("[Event \"Rated Bullet"));
+
+        let resume = games[1].1;
+        let rest = &GAMES.as_bytes()[resume.offset as usize..];
+        let stream = PgnStream::new(rest, resume.offset).unwrap();
+        let resumed = read_all(GameSplitter::new(stream, Some(resume)).unwrap());
+        assert_eq!(resumed, games[2..]);
+    }
+
+    #[test]
+    fn test_resume_zstd_frames() {
+        let data = compressed_frames();
+        let stream = PgnStream::new(io::Cursor::new(data.clone()), 0).unwrap();
+        let games = read_all(GameSplitter::new(stream, None).unwrap());
+        let texts: Vec<_> = games.iter().map(|(text, _)| text.as_str()).collect();
+        assert_eq!(texts.concat(), GAMES);
+
+        // The first two games start in the first frame, and the third one in the second frame.
+        assert_eq!(games[1].1.offset, 0);
+        assert_eq!(games[1].1.skip_games, 2);
+        let resume = games[2].1;
+        assert!(resume.offset > 0);
+        assert_eq!(resume.skip_games, 1);
+
+        let rest = data[resume.offset as usize..].to_vec();
+        let stream = PgnStream::new(io::Cursor::new(rest), resume.offset).unwrap();
+        assert!(read_all(GameSplitter::new(stream, Some(resume)).unwrap()).is_empty());
+
+        let resume = games[1].1;
+        let stream = PgnStream::new(io::Cursor::new(data), resume.offset).unwrap();
+        let resumed = read_all(GameSplitter::new(stream, Some(resume)).unwrap());
+        assert_eq!(resumed, games[2..]);
+    }
+}
@@ -0,0 +1,162 @@
+use compact_board::batch::{batch_entry, BatchEntry};
+use compact_board::evaluation::EngineEvaluation;
+use compact_board::{GameResult, LabelRecord, PositionHash};
+use shakmaty::{uci::Uci, Board, Chess, Position};
+
+use pgn_reader::{RawComment, RawHeader, SanPlus, Skip, Visitor};
+
+use crate::filter::{GameFilter, GameHeaders};
+use crate::sampling::PositionSampling;
+
+/// The boards of a game, and the labels and evaluations of its positions if they are collected.
+#[derive(Default)]
+pub struct GamePositions {
+    pub boards: Vec<(Board, PositionHash)>,
+    pub labels: Vec<LabelRecord>,
+    pub evals: Vec<BatchEntry>,
+    /// The average rating of the two players, if both are known.
+    pub average_elo: Option<u32>,
+}
+
+/// Collects the boards of the mainline of a game that the sampling keeps, together with their hash.
+///
+/// If labels are collected, every position before a move also gets a [`LabelRecord`]
+/// with the move that was played and the result and ratings from the headers.
+/// If evaluations are collected, every position with an `[%eval]` comment after the move
+/// that led to it becomes a batch entry with the move that was played next.
+///
+/// The labels and evaluations are sampled in the same way, by the position before the move.
+///
+/// Games rejected by the filter are skipped right after their headers,
+/// so their moves are never parsed, and they produce no boards.
+pub struct AllPositions {
+    positions: GamePositions,
+    current_pos: Chess,
+    current_hash: PositionHash,
+    ply: u16,
+    filter: GameFilter,
+    sampling: PositionSampling,
+    headers: GameHeaders,
+    collect_labels: bool,
+    result: GameResult,
+    collect_evals: bool,
+    /// The evaluation from the comment after the last move, which is the evaluation of `current_pos`.
+    current_eval: Option<EngineEvaluation>,
+    pub games_accepted: u64,
+    pub games_skipped: u64,
+}
+
+impl AllPositions {
+    pub fn new(
+        filter: GameFilter,
+        sampling: PositionSampling,
+        collect_labels: bool,
+        collect_evals: bool,
+    ) -> AllPositions {
+        let current_pos = Chess::new();
+        AllPositions {
+            positions: GamePositions::default(),
+            current_hash: PositionHash::of_board(current_pos.board()),
+            current_pos,
+            ply: 0,
+            filter,
+            sampling,
+            headers: GameHeaders::default(),
+            collect_labels,
+            result: GameResult::Unknown,
+            collect_evals,
+            current_eval: None,
+            games_accepted: 0,
+            games_skipped: 0,
+        }
+    }
+}
+
+impl Visitor for AllPositions {
+    type Result = GamePositions;
+
+    fn begin_headers(&mut self) {
+        self.headers = GameHeaders::default();
+        self.result = GameResult::Unknown;
+    }
+
+    fn header(&mut self, key: &[u8], value: RawHeader<'_>) {
+        self.headers.record(key, value.as_bytes());
+        if key == b"Result" {
+            let value = String::from_utf8_lossy(value.as_bytes());
+            self.result = GameResult::from_header(&value);
+        }
+    }
+
+    fn end_headers(&mut self) -> Skip {
+        let accepted = self.filter.accepts(&self.headers);
+        if accepted {
+            self.games_accepted += 1;
+            self.positions.average_elo = match (self.headers.white_elo, self.headers.black_elo) {
+                (Some(white), Some(black)) => Some((white + black) / 2),
+                _ => None,
+            };
+        } else {
+            self.games_skipped += 1;
+        }
+        Skip(!accepted)
+    }
+
+    fn begin_variation(&mut self) -> Skip {
+        Skip(true) // stay in the mainline
+    }
+
+    fn san(&mut self, san_plus: SanPlus) {
+        if let Ok(m) = san_plus.san.to_move(&self.current_pos) {
+            let keep_before =
+                self.sampling
+                    .keeps(self.current_pos.board(), self.current_hash, self.ply);
+            if self.collect_labels && keep_before {
+                let elo = |elo: Option<u32>| elo.map(|e| e.min(u16::MAX as u32) as u16);
+                self.positions.labels.push(LabelRecord {
+                    position: compact_board::position_to_compact(&self.current_pos, false),
+                    uci: Uci::from_standard(&m).to_string(),
+                    result: self.result,
+                    white_elo: elo(self.headers.white_elo),
+                    black_elo: elo(self.headers.black_elo),
+                    ply: self.ply,
+                });
+            }
+            if let Some(eval) = self.current_eval.take().filter(|_| keep_before) {
+                let uci = Uci::from_standard(&m);
+                let (board, turn) = (self.current_pos.board(), self.current_pos.turn());
+                self.positions
+                    .evals
+                    .push(batch_entry(board, turn, eval, &uci));
+            }
+            self.current_hash = self.current_hash.board_after_move(&self.current_pos, &m);
+            self.current_pos.play_unchecked(&m);
+            self.ply = self.ply.saturating_add(1);
+            if self
+                .sampling
+                .keeps(self.current_pos.board(), self.current_hash, self.ply)
+            {
+                self.positions
+                    .boards
+                    .push((self.current_pos.board().clone(), self.current_hash));
+            }
+        }
+    }
+
+    fn comment(&mut self, comment: RawComment<'_>) {
+        // A comment before the first move has no position to go with.
+        if self.collect_evals && self.ply > 0 {
+            let comment = String::from_utf8_lossy(comment.as_bytes());
+            self.current_eval = EngineEvaluation::from_pgn_comment(&comment);
+        }
+    }
+
+    fn end_game(&mut self) -> Self::Result {
+        // The evaluation of the final position has no move to go with, so it is dropped.
+        self.current_eval = None;
+        self.current_pos = Chess::new();
+        self.current_hash = PositionHash::of_board(self.current_pos.board());
+        self.ply = 0;
+        ::std::mem::take(&mut self.positions)
+    }
+}
@@ -1,6 +1,6 @@
 use std::io::Read;
 
-use compact_board::compact_slice_to_board;
+use compact_board::CompactBoard;
 use shakmaty::{uci::Uci, Role, Square};
 use tch::{data::Iter2, Tensor};
 
@@ -17,14 +17,17 @@ pub fn load_batch(n: usize) -> Iter2 {
     reader.read_to_end(&mut data).unwrap();
 
     //    let data: Vec<(Vec<u8>, f32, String)> = postcard::from_io((reader, &mut buf)).unwrap().0;
-    let data: Vec<(Vec<u8>, f32, String)> = postcard::from_bytes(&data).unwrap();
+    let data: Vec<(CompactBoard, f32, String)> = postcard::from_bytes(&data).unwrap();
 
     // Now convert it into an input and output tensor.
     let mut inputs = vec![];
     let mut outputs = vec![];
 
     for datum in data.iter() {
-        let board = compact_slice_to_board(&datum.0).unwrap();
+        let board = datum
+            .0
+            .try_to_board(true)
+            .unwrap_or_else(|e| panic!("Corrupted board in batch_{n}: {e}"));
         let board_vector = board_to_vector(&board, true);
         inputs.extend_from_slice(&board_vector);
         let uci = Uci::from_ascii(datum.2.as_bytes()).unwrap();
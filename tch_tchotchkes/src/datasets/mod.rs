@@ -1,6 +1,6 @@
 use std::{io::Read, num::NonZeroU32};
 
-use compact_board::compact_slice_to_board;
+use compact_board::{CompactBoard, LabelRecord, Transform};
 use shakmaty::{san::San, uci::Uci, Bitboard, Chess, FromSetup, Piece, Position, Setup};
 use tch::{data::Iter2, Tensor};
 
@@ -17,14 +17,17 @@ pub fn load_batch_only_evaluation(n: u64, separate_pos_neg: bool) -> Iter2 {
     reader.read_to_end(&mut data).unwrap();
 
     //    let data: Vec<(Vec<u8>, f32, String)> = postcard::from_io((reader, &mut buf)).unwrap().0;
-    let data: Vec<(Vec<u8>, f32, String)> = postcard::from_bytes(&data).unwrap();
+    let data: Vec<(CompactBoard, f32, String)> = postcard::from_bytes(&data).unwrap();
 
     // Now convert it into an input and output tensor.
     let mut inputs = vec![];
     let mut outputs = vec![];
 
     for datum in data.iter() {
-        let board = compact_slice_to_board(&datum.0).unwrap();
+        let board = datum
+            .0
+            .try_to_board(true)
+            .unwrap_or_else(|e| panic!("Corrupted board in batch_{n}: {e}"));
         let board_vector = board_to_vector(&board, false);
         inputs.extend_from_slice(&board_vector);
         if separate_pos_neg {
@@ -58,14 +61,17 @@ pub fn load_batch_board_states(n: u64) -> Iter2 {
     reader.read_to_end(&mut data).unwrap();
 
     //    let data: Vec<(Vec<u8>, f32, String)> = postcard::from_io((reader, &mut buf)).unwrap().0;
-    let data: Vec<(Vec<u8>, f32, String)> = postcard::from_bytes(&data).unwrap();
+    let data: Vec<(CompactBoard, f32, String)> = postcard::from_bytes(&data).unwrap();
 
     // Now convert it into an input and output tensor.
     let mut inputs = vec![];
     let mut outputs = vec![];
 
     for datum in data.iter() {
-        let board = compact_slice_to_board(&datum.0).unwrap();
+        let board = datum
+            .0
+            .try_to_board(true)
+            .unwrap_or_else(|e| panic!("Corrupted board in batch_{n}: {e}"));
         let act = Uci::from_ascii(datum.2.as_bytes()).unwrap();
         let mut new_board = board.clone();
         if let Uci::Normal {
@@ -101,3 +107,102 @@ pub fn load_batch_board_states(n: u64) -> Iter2 {
 
     Iter2::new(&input_tensor, &output_tensor, 100)
 }
+
+fn read_label_batch(name: &str, n: u64) -> Vec<LabelRecord> {
+    println!("Loading labels_{n} of {name}...");
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .open(format!("../hugedata/labels/{name}/labels_{n}.postcard"))
+        .unwrap();
+    let mut reader = std::io::BufReader::new(file);
+    let mut data = vec![];
+    reader.read_to_end(&mut data).unwrap();
+    postcard::from_bytes(&data).unwrap()
+}
+
+/// The board of the label, seen from the side to move,
+/// so that the side to move always plays the white pieces up the board,
+/// together with the transform that was applied.
+fn label_board(label: &LabelRecord, name: &str, n: u64) -> (shakmaty::Board, Transform) {
+    let pos = compact_board::compact_slice_to_position(&label.position)
+        .unwrap_or_else(|e| panic!("Corrupted position in labels_{n} of {name}: {e}"));
+    let transform = Transform {
+        color_flip: pos.turn().is_black(),
+        mirror: false,
+    };
+    (transform.board(pos.board()), transform)
+}
+
+/// Loads the positions of a batch of labels extracted by `position_extractor --labels`,
+/// with the move that was played as the output: `[from, to, promotion role + 1 or 0]`.
+pub fn load_label_batch_policy(name: &str, n: u64) -> Iter2 {
+    let data = read_label_batch(name, n);
+
+    let mut inputs = vec![];
+    let mut outputs = vec![];
+
+    for label in data.iter() {
+        let (board, transform) = label_board(label, name, n);
+        inputs.extend_from_slice(&board_to_vector(&board, true));
+
+        let uci = Uci::from_ascii(label.uci.as_bytes()).unwrap();
+        let mut move_dest = [0i64; 3];
+        if let Uci::Normal {
+            from,
+            to,
+            promotion,
+        } = transform.uci(&uci)
+        {
+            move_dest[0] = from as i64;
+            move_dest[1] = to as i64;
+            if let Some(promo) = promotion {
+                move_dest[2] = promo as i64 + 1;
+            }
+        } else {
+            panic!("Unexpected kind of UCI move: {uci:?}");
+        }
+        outputs.extend_from_slice(&move_dest);
+    }
+
+    let input_tensor = Tensor::from_slice(&inputs).view((data.len() as i64, 2 * 6 * 64));
+    let output_tensor = Tensor::from_slice(&outputs).view((data.len() as i64, 3));
+
+    println!("Input shape: {:?}", input_tensor.size());
+    println!("Output shape: {:?}", output_tensor.size());
+
+    Iter2::new(&input_tensor, &output_tensor, 100)
+}
+
+/// Loads the positions of a batch of labels extracted by `position_extractor --labels`,
+/// with the result of the game for the side to move as the output.
+/// Positions from games without a known result are left out.
+pub fn load_label_batch_outcome(name: &str, n: u64) -> Iter2 {
+    let data = read_label_batch(name, n);
+
+    let mut inputs = vec![];
+    let mut outputs = vec![];
+
+    for label in data.iter() {
+        let (board, transform) = label_board(label, name, n);
+        // The side to move in the game, before the colour flip.
+        let turn = if transform.color_flip {
+            shakmaty::Color::Black
+        } else {
+            shakmaty::Color::White
+        };
+        let Some(score) = label.result.score_for(turn) else {
+            continue;
+        };
+        inputs.extend_from_slice(&board_to_vector(&board, false));
+        outputs.push(score);
+    }
+
+    let count = outputs.len() as i64;
+    let input_tensor = Tensor::from_slice(&inputs).view((count, 2 * 6 * 64));
+    let output_tensor = Tensor::from_slice(&outputs).view((count, 1));
+
+    println!("Input shape: {:?}", input_tensor.size());
+    println!("Output shape: {:?}", output_tensor.size());
+
+    Iter2::new(&input_tensor, &output_tensor, 100)
+}
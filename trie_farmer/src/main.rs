@@ -1,102 +1,133 @@
-use std::collections::HashMap;
-
+//! Merges the counts of different months in `../hugedata`.
+//!
+//! The files to merge are found in the manifest of the directory, see [`dataset_manifest`].
+//! Any two files of the same kind, made with the same options for different months, can be merged,
+//! whether their months are consecutive or not.
+//!
+//! Usage:
+//!
+//! - `trie_farmer`: merges the two tries of the fewest months that can be merged,
+//!   either board tries or position stats, with both tries loaded into memory.
+//! - `trie_farmer merge-records [--min-count N] [NAME...]`: merges any number of record files
+//!   of board counts in one pass, holding only one record of every file in memory.
+//!   See [`records`].
+//! - `trie_farmer to-records [NAME...]`: converts board tries into record files, one at a time.
+
+use std::path::Path;
+
+use compact_board::PositionStats;
+use dataset_manifest::{write_postcard_checked, Artifact, ArtifactKind, Manifest};
 use radix_trie::{Trie, TrieCommon};
+use serde::{de::DeserializeOwned, Serialize};
 
-type Date = (i32, i32);
+mod records;
 
-fn next_date(cur: Date) -> Date {
-    let (mut current_year, mut current_month) = cur;
-    current_month += 1;
-    if current_month > 12 {
-        current_month = 1;
-        current_year += 1;
-    }
-    (current_year, current_month)
+const DATA_DIR: &str = "../hugedata";
+
+/// A kind of trie that is written for every month, and can be merged into a combined one.
+struct TrieKind {
+    artifact: ArtifactKind,
+    /// The end of the names of the combined tries, with keys in the given encoding version.
+    combined_suffix: fn(u8) -> String,
 }
 
-fn get_date_range(name: &str) -> Vec<Date> {
-    if let Some(a) = name.strip_prefix("single-") {
-        if let Some(b) = a.strip_suffix("-board-trie.postcard") {
-            let parts: Vec<_> = b.split("-").collect();
-            let year = parts[0].parse().unwrap();
-            let month = parts[1].parse().unwrap();
-            return vec![(year, month)];
-        }
-    }
+const BOARD_TRIES: TrieKind = TrieKind {
+    artifact: ArtifactKind::BoardTrie,
+    combined_suffix: |encoding_version| dataset_manifest::board_trie_suffix(encoding_version, true),
+};
 
-    return vec![];
-
-    if let Some(a) = name.strip_suffix("-board-tries.postcard") {
-        if let Some(b) = a.strip_prefix("combined-") {
-            let parts: Vec<_> = b.split("+").collect();
-            let left_parts: Vec<_> = parts[0].split("-").collect();
-            let right_parts: Vec<_> = parts[1].split("-").collect();
-            let left_year: i32 = (left_parts[0]).parse().unwrap();
-            let left_month: i32 = (left_parts[1]).parse().unwrap();
-            let right_year = (right_parts[0]).parse().unwrap();
-            let right_month = (right_parts[1]).parse().unwrap();
-            let mut current_month = left_month;
-            let mut current_year = left_year;
-            let mut output = vec![];
-            while !(current_month == right_month && current_year == right_year) {
-                output.push((current_year, current_month));
-                (current_year, current_month) = next_date((current_year, current_month));
-            }
-            output.push((current_year, current_month));
+const POSITION_STATS: TrieKind = TrieKind {
+    artifact: ArtifactKind::PositionStats,
+    combined_suffix: |_| "-position-stats.postcard".to_string(),
+};
 
-            return output;
-        }
+/// The values of a kind of trie, which are added up when tries are merged.
+trait TrieValue: Clone + Serialize + DeserializeOwned + Send + 'static {
+    /// Adds the counts of `other`, the value of the same key in the other trie.
+    fn merge(&mut self, other: &Self);
+
+    /// Whether the key was only seen once.
+    fn is_unique(&self) -> bool;
+}
+
+/// The number of times a board was seen, where 0 means once.
+impl TrieValue for usize {
+    fn merge(&mut self, other: &Self) {
+        // Both values are one less than their count, so the sum is one short.
+        *self += other + 1;
     }
 
-    vec![]
+    fn is_unique(&self) -> bool {
+        *self == 0
+    }
 }
 
-/// Check if the left range ends at the time that the right one begins.
-/// This makes them suitable for joining.
-///
-/// The ranges need to be sorted, and in the correct order,
-/// for this to return true.
-fn are_adjacent(left: &[Date], right: &[Date]) -> bool {
-    if left.is_empty() || right.is_empty() {
-        return false;
+impl TrieValue for PositionStats {
+    fn merge(&mut self, other: &Self) {
+        PositionStats::merge(self, other);
+    }
+
+    fn is_unique(&self) -> bool {
+        self.games <= 1
     }
-    println!("{left:?} {right:?}");
-    let left_end = left.last().unwrap();
-    let right_begin = right.first().unwrap();
-    next_date(*left_end) == *right_begin
 }
 
 #[tokio::main]
 async fn main() {
-    // List the files in the target directory and check if they are board tries, and for what dates.
-    let mut ranges = HashMap::new();
-    for file in std::fs::read_dir("../hugedata").unwrap() {
-        let file_name = file.unwrap().file_name();
-        let name = file_name.to_string_lossy();
-        let range = get_date_range(&name);
-        ranges.insert(name.to_string(), range);
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("merge-records") => return records::merge_records(&args[1..]),
+        Some("to-records") => return records::to_records(&args[1..]),
+        _ => {}
+    }
+
+    if merge_smallest::<usize>(&BOARD_TRIES).await {
+        return;
+    }
+    if merge_smallest::<PositionStats>(&POSITION_STATS).await {
+        return;
     }
 
-    for (left_name, left_range) in ranges.iter() {
-        for (right_name, right_range) in ranges.iter() {
-            if are_adjacent(&left_range, &right_range) {
-                perform_merge(left_name, right_name, left_range, right_range).await;
-                return;
+    println!("No merges possible currently");
+}
+
+/// Merges the two tries of the kind that can be merged and cover the fewest months between them,
+/// so that the tries loaded into memory stay small, and returns whether there were any.
+async fn merge_smallest<V: TrieValue>(kind: &TrieKind) -> bool {
+    let manifest = Manifest::load(Path::new(DATA_DIR)).unwrap();
+    let tries: Vec<_> = manifest.of_kind(kind.artifact).collect();
+    let mut best = None;
+    for (i, left) in tries.iter().enumerate() {
+        for right in tries[i + 1..].iter() {
+            if !left.1.can_merge_with(right.1) {
+                continue;
+            }
+            let months = left.1.months.len() + right.1.months.len();
+            if best.is_some_and(|(best_months, _, _)| best_months <= months) {
+                continue;
             }
+            best = Some((months, *left, *right));
         }
     }
 
-    println!("No merges possible currently");
+    let Some((_, (left_name, left), (right_name, right))) = best else {
+        return false;
+    };
+    perform_merge::<V>((left_name, left), (right_name, right), kind).await;
+    true
 }
 
-async fn perform_merge(
-    left_name_ref: &str,
-    right_name_ref: &str,
-    left_range: &[(i32, i32)],
-    right_range: &[(i32, i32)],
+async fn perform_merge<V: TrieValue>(
+    (left_name_ref, left): (&String, &Artifact),
+    (right_name_ref, right): (&String, &Artifact),
+    kind: &TrieKind,
 ) {
     let left_name = left_name_ref.to_string();
     let right_name = right_name_ref.to_string();
+    left.verify(&Path::new(DATA_DIR).join(&left_name)).unwrap();
+    right
+        .verify(&Path::new(DATA_DIR).join(&right_name))
+        .unwrap();
 
     let left_file_proc = tokio::task::spawn_blocking(move || {
         println!("Loading left file {left_name}...");
@@ -106,7 +137,7 @@ async fn perform_merge(
             .unwrap();
         let reader = std::io::BufReader::new(left_file);
         let mut buf = [0; 32 * 1024];
-        let left_trie: radix_trie::Trie<Vec<u8>, usize> =
+        let left_trie: radix_trie::Trie<Vec<u8>, V> =
             postcard::from_io((reader, &mut buf)).unwrap().0;
         println!("Loading left file completed!");
         left_trie
@@ -120,13 +151,13 @@ async fn perform_merge(
         let mut buf = [0; 32 * 1024];
 
         let reader = std::io::BufReader::new(right_file);
-        let right_trie: radix_trie::Trie<Vec<u8>, usize> =
+        let right_trie: radix_trie::Trie<Vec<u8>, V> =
             postcard::from_io((reader, &mut buf)).unwrap().0;
         println!("Loading right file completed!");
         right_trie
     });
-    let mut left_trie: Trie<Vec<u8>, usize> = left_file_proc.await.unwrap();
-    let right_trie: Trie<Vec<u8>, usize> = right_file_proc.await.unwrap();
+    let mut left_trie: Trie<Vec<u8>, V> = left_file_proc.await.unwrap();
+    let right_trie: Trie<Vec<u8>, V> = right_file_proc.await.unwrap();
 
     let left_name = left_name_ref.to_string();
     let right_name = right_name_ref.to_string();
@@ -136,44 +167,72 @@ async fn perform_merge(
     println!("Left: {}", before);
     println!("Right: {}", right_trie.len());
     println!("Uniques counts:");
-    let left_unique_count = left_trie.values().filter(|v| **v == 0).count();
+    let left_unique_count = left_trie.values().filter(|v| v.is_unique()).count();
     println!("Left: {left_unique_count}");
-    let right_unique_count = right_trie.values().filter(|v| **v == 0).count();
+    let right_unique_count = right_trie.values().filter(|v| v.is_unique()).count();
     println!("Right: {right_unique_count}");
 
-    let total_entries = right_trie.len();
-    let mut remaining_entries = right_trie.len();
-    for (k, right_v) in right_trie.iter() {
-        left_trie.map_with_default(k.clone(), |left_v| *left_v += right_v, *right_v);
-        remaining_entries -= 1;
-        if remaining_entries % 1000 == 0 {
-            println!("Remaining: {remaining_entries}\t/\t{total_entries}");
-        }
-    }
+    merge_tries(&mut left_trie, &right_trie);
 
     println!("Before: {before}");
     println!("After: {}", left_trie.len());
 
-    let unique_count = left_trie.values().filter(|v| **v == 0).count();
+    let unique_count = left_trie.values().filter(|v| v.is_unique()).count();
     println!("New unique count: {unique_count}");
 
     println!("Completed merge in memory, writing to disk");
-    let new_left = left_range.first().unwrap();
-    let new_right = right_range.last().unwrap();
-
-    let file = std::fs::OpenOptions::new()
-        .create(true)
-        .write(true)
-        .open(format!(
-            "../hugedata/combined-{}-{}+{}-{}-board-tries.postcard",
-            new_left.0, new_left.1, new_right.0, new_right.1
-        ))
-        .unwrap();
-    let buf = std::io::BufWriter::new(file);
-    println!("New file covering {new_left:?} to {new_right:?} ready, writing...");
-    postcard::to_io(&left_trie, buf).unwrap();
+    let mut merged = left.merged_with(right);
+    let suffix = (kind.combined_suffix)(merged.encoding_version);
+    let merged_name = dataset_manifest::combined_name(&merged.months, &suffix);
+    let merged_path = Path::new(DATA_DIR).join(&merged_name);
+
+    println!("New file {merged_name} ready, writing...");
+    let crc32 = write_postcard_checked(&merged_path, &left_trie).unwrap();
+
+    merged.entries = Some(left_trie.len() as u64);
+    merged.crc32 = Some(crc32);
+    Manifest::update(Path::new(DATA_DIR), |manifest| {
+        manifest.artifacts.remove(&left_name);
+        manifest.artifacts.remove(&right_name);
+        manifest.artifacts.insert(merged_name, merged);
+    })
+    .unwrap();
 
     println!("Write completed! deleting source files");
     std::fs::remove_file(format!("../hugedata/{left_name}")).unwrap();
     std::fs::remove_file(format!("../hugedata/{right_name}")).unwrap();
 }
+
+/// Adds the values of `right_trie` to those of the same keys in `left_trie`.
+fn merge_tries<V: TrieValue>(left_trie: &mut Trie<Vec<u8>, V>, right_trie: &Trie<Vec<u8>, V>) {
+    let total_entries = right_trie.len();
+    let mut remaining_entries = right_trie.len();
+    for (k, right_v) in right_trie.iter() {
+        left_trie.map_with_default(k.clone(), |left_v| left_v.merge(right_v), right_v.clone());
+        remaining_entries -= 1;
+        if remaining_entries % 1000 == 0 {
+            println!("Remaining: {remaining_entries}\t/\t{total_entries}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_merge_boards_seen_once() {
+        let mut left = Trie::new();
+        left.insert(vec![1], 0usize);
+        left.insert(vec![2], 0usize);
+        let mut right = Trie::new();
+        right.insert(vec![1], 0usize);
+        right.insert(vec![3], 2usize);
+
+        merge_tries(&mut left, &right);
+        // Seen once in each month makes twice, which is stored as 1.
+        assert_eq!(left.get(&vec![1]), Some(&1));
+        assert_eq!(left.get(&vec![2]), Some(&0));
+        assert_eq!(left.get(&vec![3]), Some(&2));
+    }
+}
@@ -0,0 +1,179 @@
+//! Merging record files of board counts, as written by `position_extractor --output-format records`.
+//!
+//! The records of a file are sorted by key, so any number of them can be merged
+//! in a single pass with [`MergedRecords`], without loading any of them into memory.
+//! Board tries can be converted into record files first with `to-records`.
+
+use std::io::{self, BufReader};
+use std::path::Path;
+
+use compact_board::{
+    check_record_count, write_trie_as_records, FormatVersion, MergedRecords, RecordReader,
+    RecordWriter,
+};
+use dataset_manifest::{write_checked, ArtifactKind, AtomicWriter, Manifest};
+use radix_trie::{Trie, TrieCommon};
+
+use crate::DATA_DIR;
+
+const RECORDS_SUFFIX: &str = "-board-counts.records";
+
+/// Merges the record files with the given names, or all the record files in the manifest of `../hugedata`,
+/// into one named after their months, and deletes them.
+///
+/// The files must be made with the same options, for different months, which do not have to be consecutive.
+/// With `--min-count N`, only the boards seen at least `N` times in total are kept.
+pub fn merge_records(args: &[String]) {
+    let mut min_count = 1;
+    let mut names = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--min-count" {
+            let value = args.next().expect("--min-count needs a value");
+            min_count = value.parse().expect("--min-count should be a number");
+        } else {
+            names.push(arg.clone());
+        }
+    }
+    let data_dir = Path::new(DATA_DIR);
+    let manifest = Manifest::load(data_dir).unwrap();
+    if names.is_empty() {
+        names = manifest
+            .of_kind(ArtifactKind::BoardCounts)
+            .map(|(name, _)| name.clone())
+            .collect();
+    }
+    if names.len() < 2 {
+        println!("Fewer than two record files, nothing to merge");
+        return;
+    }
+
+    let mut merged = None;
+    for name in names.iter() {
+        let Some(artifact) = manifest.artifacts.get(name) else {
+            println!("{name} is not in the manifest");
+            return;
+        };
+        if artifact.kind != ArtifactKind::BoardCounts {
+            println!("{name} is not a record file of board counts");
+            return;
+        }
+        artifact.verify(&data_dir.join(name)).unwrap();
+        merged = match merged {
+            None => Some(artifact.clone()),
+            Some(merged) if merged.can_merge_with(artifact) => Some(merged.merged_with(artifact)),
+            Some(_) => {
+                println!("{name} overlaps the months of the other files or was made differently, not merging");
+                return;
+            }
+        };
+    }
+
+    let mut merged = merged.unwrap();
+    let output = dataset_manifest::combined_name(&merged.months, RECORDS_SUFFIX);
+    println!("Merging {} record files into {output}", names.len());
+    let (read, written, crc32) = merge_files(&names, &output, min_count).unwrap();
+    println!("Merged {read} different boards, and kept {written} of them");
+
+    merged.min_count = merged.min_count.max(min_count);
+    merged.entries = Some(written);
+    merged.crc32 = Some(crc32);
+    Manifest::update(data_dir, |manifest| {
+        for name in names.iter() {
+            manifest.artifacts.remove(name);
+        }
+        manifest.artifacts.insert(output, merged);
+    })
+    .unwrap();
+
+    println!("Write completed! deleting source files");
+    for name in names {
+        std::fs::remove_file(format!("../hugedata/{name}")).unwrap();
+    }
+}
+
+/// Merges the record files into a new one, keeping the boards seen at least `min_count` times,
+/// and returns the number of different boards read and written, and the CRC-32 of the new file.
+fn merge_files(names: &[String], output: &str, min_count: u64) -> io::Result<(u64, u64, u32)> {
+    let mut readers = vec![];
+    for name in names {
+        let file = std::fs::File::open(format!("../hugedata/{name}"))?;
+        readers.push(RecordReader::new(BufReader::new(file))?);
+    }
+    let write = |out: &mut AtomicWriter| {
+        let mut writer = RecordWriter::new(out)?;
+        let mut read = 0;
+        for record in MergedRecords::new(readers)? {
+            let (board, count) = record?;
+            read += 1;
+            if count >= min_count {
+                writer.push(&board, count)?;
+            }
+            if read % 1_000_000 == 0 {
+                println!("Merged: {read}\tkept: {}", writer.len());
+            }
+        }
+        let written = writer.len();
+        writer.finish()?;
+        Ok((read, written))
+    };
+    let check = |path: &Path, (_, written): &(u64, u64)| check_record_count(path, *written);
+    let ((read, written), crc32) = write_checked(&Path::new(DATA_DIR).join(output), write, check)?;
+    Ok((read, written, crc32))
+}
+
+/// Converts the board tries with the given names, or all the board tries in the manifest of `../hugedata`,
+/// into record files with the same name ending in `-board-counts.records` instead.
+/// The tries are kept. Tries recoded by `trie_recoder` are skipped,
+/// since the names of their record files would be the same as those of the V1 tries.
+pub fn to_records(args: &[String]) {
+    let data_dir = Path::new(DATA_DIR);
+    let manifest = Manifest::load(data_dir).unwrap();
+    let mut names = args.to_vec();
+    if names.is_empty() {
+        names = manifest
+            .of_kind(ArtifactKind::BoardTrie)
+            .map(|(name, _)| name.clone())
+            .collect();
+    }
+
+    for name in names {
+        let artifact = match manifest.artifacts.get(&name) {
+            Some(artifact) if artifact.kind == ArtifactKind::BoardTrie => artifact,
+            _ => {
+                println!("{name} is not a board trie in the manifest, skipping");
+                continue;
+            }
+        };
+        if artifact.encoding_version != FormatVersion::V1.tag() {
+            println!("{name} does not have V1 keys, skipping");
+            continue;
+        }
+        let stem = name
+            .strip_suffix("-board-trie.postcard")
+            .or_else(|| name.strip_suffix("-board-tries.postcard"))
+            .unwrap_or(&name);
+        let output = format!("{stem}{RECORDS_SUFFIX}");
+        println!("Loading file {name}...");
+        artifact.verify(&data_dir.join(&name)).unwrap();
+        let file = std::fs::File::open(format!("../hugedata/{name}")).unwrap();
+        let mut buf = [0; 32 * 1024];
+        let trie: Trie<Vec<u8>, usize> = postcard::from_io((BufReader::new(file), &mut buf))
+            .unwrap()
+            .0;
+
+        println!("Writing {} boards to {output}...", trie.len());
+        let write = |out: &mut AtomicWriter| write_trie_as_records(&trie, out);
+        let check = |path: &Path, _: &u64| check_record_count(path, trie.len() as u64);
+        let (_, crc32) = write_checked(&data_dir.join(&output), write, check).unwrap();
+
+        let mut records = artifact.clone();
+        records.kind = ArtifactKind::BoardCounts;
+        records.entries = Some(trie.len() as u64);
+        records.crc32 = Some(crc32);
+        Manifest::update(data_dir, |manifest| {
+            manifest.artifacts.insert(output, records);
+        })
+        .unwrap();
+    }
+}
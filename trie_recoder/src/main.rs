@@ -0,0 +1,147 @@
+//! Re-encodes board tries with a different compact board layout,
+//! and reports how much smaller the keys become.
+//!
+//! Usage: `trie_recoder [--write] [NAME...]`
+//!
+//! The names are files in `../hugedata`; if none are given, every board trie there is used.
+//! Only the tries with unversioned [`FormatVersion::V1`] keys in the manifest are recoded.
+//! Their keys are re-encoded as versioned [`FormatVersion::V2`] boards,
+//! so the new keys say which layout they use.
+//! The new trie is named like the old one, but ending in `-board-keys-v2.postcard`,
+//! and is recorded in the manifest with its encoding version,
+//! so that the tools reading V1 keys skip it.
+//! Without `--write`, only the sizes are reported and nothing is written.
+
+use std::path::Path;
+
+use compact_board::FormatVersion;
+use dataset_manifest::{write_postcard_checked, Artifact, ArtifactKind, Manifest};
+use radix_trie::{Trie, TrieCommon};
+
+const DATA_DIR: &str = "../hugedata";
+
+const TARGET_VERSION: FormatVersion = FormatVersion::V2;
+
+fn main() {
+    let mut write = false;
+    let mut names = vec![];
+    for arg in std::env::args().skip(1) {
+        if arg == "--write" {
+            write = true;
+        } else {
+            names.push(arg);
+        }
+    }
+
+    let manifest = Manifest::load(Path::new(DATA_DIR)).unwrap();
+    if names.is_empty() {
+        names = manifest
+            .of_kind(ArtifactKind::BoardTrie)
+            .map(|(name, _)| name.clone())
+            .collect();
+    }
+
+    for name in names {
+        match manifest.artifacts.get(&name) {
+            Some(artifact)
+                if artifact.kind == ArtifactKind::BoardTrie
+                    && artifact.encoding_version == FormatVersion::V1.tag() =>
+            {
+                recode_trie(&name, artifact, write)
+            }
+            _ => println!("{name} is not a board trie with V1 keys in the manifest, skipping"),
+        }
+    }
+}
+
+fn recode_trie(name: &str, artifact: &Artifact, write: bool) {
+    println!("Recoding trie {name}");
+    artifact.verify(&Path::new(DATA_DIR).join(name)).unwrap();
+    println!("Loading it into memory...");
+    let f = std::fs::OpenOptions::new()
+        .read(true)
+        .open(format!("../hugedata/{name}"))
+        .unwrap();
+    let size_before = f.metadata().unwrap().len();
+    let reader = std::io::BufReader::new(f);
+    let mut buf = [0; 32 * 1024];
+    let trie: Trie<Vec<u8>, usize> = postcard::from_io((reader, &mut buf)).unwrap().0;
+    println!("Loading file completed!");
+
+    let mut new_trie = Trie::new();
+    let mut key_bytes_before = 0;
+    let mut key_bytes_after = 0;
+    let mut corrupted = 0;
+    for (k, v) in trie.iter() {
+        let board = match compact_board::try_compact_slice_to_board(k, false) {
+            Ok(board) => board,
+            Err(e) => {
+                println!("Skipping corrupted board {k:?}: {e}");
+                corrupted += 1;
+                continue;
+            }
+        };
+        let new_key = compact_board::board_to_versioned_compact(&board, TARGET_VERSION);
+        key_bytes_before += k.len();
+        key_bytes_after += new_key.len();
+        new_trie.insert(new_key, *v);
+    }
+    drop(trie);
+
+    let count = new_trie.len();
+    println!("Boards: \t{count}");
+    println!("Corrupted: \t{corrupted}");
+    println!("Key bytes before: \t{key_bytes_before}");
+    println!(
+        "Key bytes after: \t{key_bytes_after} ({:.1}%)",
+        percentage(key_bytes_after as u64, key_bytes_before as u64)
+    );
+    if count > 0 {
+        println!(
+            "Average key length: \t{:.2} -> {:.2} bytes",
+            key_bytes_before as f64 / count as f64,
+            key_bytes_after as f64 / count as f64
+        );
+    }
+
+    if !write {
+        println!("File size before: \t{size_before}");
+        println!("Not writing, pass --write to save the recoded trie");
+        return;
+    }
+
+    let stem = name
+        .strip_suffix("-board-trie.postcard")
+        .or_else(|| name.strip_suffix("-board-tries.postcard"))
+        .unwrap_or(name);
+    let combined = artifact.months.len() > 1;
+    let new_name = format!(
+        "{stem}{}",
+        dataset_manifest::board_trie_suffix(TARGET_VERSION.tag(), combined)
+    );
+    println!("Writing {new_name}...");
+    let new_path = Path::new(DATA_DIR).join(&new_name);
+    let crc32 = write_postcard_checked(&new_path, &new_trie).unwrap();
+    let size_after = std::fs::metadata(&new_path).unwrap().len();
+
+    let mut recoded = artifact.clone();
+    recoded.encoding_version = TARGET_VERSION.tag();
+    recoded.entries = Some(count as u64);
+    recoded.crc32 = Some(crc32);
+    Manifest::update(Path::new(DATA_DIR), |manifest| {
+        manifest.artifacts.insert(new_name, recoded);
+    })
+    .unwrap();
+    println!("File size before: \t{size_before}");
+    println!(
+        "File size after: \t{size_after} ({:.1}%)",
+        percentage(size_after, size_before)
+    );
+}
+
+fn percentage(part: u64, whole: u64) -> f64 {
+    if whole == 0 {
+        return 100.0;
+    }
+    part as f64 * 100.0 / whole as f64
+}
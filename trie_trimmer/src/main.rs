@@ -1,3 +1,6 @@
+use std::path::Path;
+
+use dataset_manifest::{write_postcard_checked, Artifact, ArtifactKind, Manifest};
 use radix_trie::TrieCommon;
 
 fn main() {
@@ -6,17 +9,20 @@ fn main() {
     println!("Press ^C within 10 seconds to cancel...");
     std::thread::sleep(std::time::Duration::from_secs(10));
 
-    for file in std::fs::read_dir("../hugedata").unwrap() {
-        let file_name = file.unwrap().file_name();
-        let name = file_name.to_string_lossy();
-        if name.contains("board-trie") {
-            trim_trie(&name);
+    let manifest = Manifest::load(Path::new("../hugedata")).unwrap();
+    for (name, artifact) in manifest.of_kind(ArtifactKind::BoardTrie) {
+        if artifact.min_count >= 2 {
+            println!("Skipping trie {name}, which has no unique boards");
+            continue;
         }
+        trim_trie(name, artifact);
     }
 }
 
-fn trim_trie(name: &str) {
+fn trim_trie(name: &str, artifact: &Artifact) {
     println!("Trimming trie {name}");
+    let path = Path::new("../hugedata").join(name);
+    artifact.verify(&path).unwrap();
     println!("Loading it into memory...");
     let f = std::fs::OpenOptions::new()
         .read(true)
@@ -53,13 +59,16 @@ fn trim_trie(name: &str) {
     println!("Trimming complete");
     println!("Length before: \t{before}");
     println!("Length now: \t{}", trie.len());
-    println!("Now overwriting {name}: DO NOT CLOSE PROGRAM NOW...");
-    let file = std::fs::OpenOptions::new()
-        .write(true)
-        .truncate(true)
-        .open(format!("../hugedata/{name}",))
-        .unwrap();
-    let buf = std::io::BufWriter::new(file);
-    postcard::to_io(&trie, buf).unwrap();
+    println!("Now overwriting {name}...");
+    let crc32 = write_postcard_checked(&path, &trie).unwrap();
     println!("Written!");
+
+    let mut trimmed = artifact.clone();
+    trimmed.min_count = 2;
+    trimmed.entries = Some(trie.len() as u64);
+    trimmed.crc32 = Some(crc32);
+    Manifest::update(Path::new("../hugedata"), |manifest| {
+        manifest.artifacts.insert(name.to_string(), trimmed);
+    })
+    .unwrap();
 }